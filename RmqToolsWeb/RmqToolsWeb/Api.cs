@@ -5,42 +5,119 @@ namespace RmqToolsWeb;
 
 public class Api(HttpClient http)
 {
+    // name of the broker connection that the requests go to, one of EnvInfo.Connections
+    public string Connection { get; set; } = "default";
+
+    // vhost of the broker, null for the vhost of the connection
+    public string? Vhost { get; set; }
+
+    string Url(string path)
+    {
+        var url = $"/api/connections/{Connection}{path}";
+        if (Vhost == null)
+            return url;
+        return url + (path.Contains('?') ? "&" : "?") + $"vhost={Uri.EscapeDataString(Vhost)}";
+    }
+
+    public string WebsocketPath() => Url("/ws");
+
     public async Task DeleteMessagesAsync(uint queueId, IEnumerable<uint> messageIds)
     {
         var body = new DeleteMessagesRequest(messageIds);
-        using var httpRequestMessage = new HttpRequestMessage(HttpMethod.Delete, $"/api/queues/{queueId}/messages");
+        using var httpRequestMessage = new HttpRequestMessage(HttpMethod.Delete, Url($"/queues/{queueId}/messages"));
         httpRequestMessage.Content = new ByteArrayContent(JsonSerializer.SerializeToUtf8Bytes(body, MySourceGenerationContext.Default.DeleteMessagesRequest));
         httpRequestMessage.Content.Headers.ContentType = new("application/json");
         using var response = await http.SendAsync(httpRequestMessage);
-        response.EnsureSuccessStatusCode();
+        await WaitForJobAsync(response);
     }
     
-    public async Task SendMessagesToQueueAsync(uint queueId, IEnumerable<uint> messageIds, string moveToQueue, int delay = 0)
+    public async Task<SendMessagesResponse> SendMessagesToQueueAsync(uint queueId, IEnumerable<uint> messageIds, string moveToQueue, int delay = 0)
     {
         var body = new SendMessagesRequest(moveToQueue, messageIds, delay);
-        using var response = await http.PostAsJsonAsync($"/api/queues/{queueId}/messages/send", body, MySourceGenerationContext.Default.SendMessagesRequest);
-        response.EnsureSuccessStatusCode();
+        using var response = await http.PostAsJsonAsync(Url($"/queues/{queueId}/messages/send"), body, MySourceGenerationContext.Default.SendMessagesRequest);
+        var job = await WaitForJobAsync(response);
+        return job.Result!.Value.Deserialize(MySourceGenerationContext.Default.SendMessagesResponse)!;
+    }
+
+    public async Task<SendMessagesResponse> ReplayMessagesToOriginAsync(uint queueId, IEnumerable<uint> messageIds, string xDeath = "keep")
+    {
+        var body = new SendMessagesRequest("", messageIds, SendDelayMs: 0, ToDeadLetterOrigin: true, XDeath: xDeath);
+        using var response = await http.PostAsJsonAsync(Url($"/queues/{queueId}/messages/send"), body, MySourceGenerationContext.Default.SendMessagesRequest);
+        var job = await WaitForJobAsync(response);
+        return job.Result!.Value.Deserialize(MySourceGenerationContext.Default.SendMessagesResponse)!;
     }
 
     public async Task<List<QueueSummary>> GetQueueSummariesAsync()
     {
-        return (await http.GetFromJsonAsync<List<QueueSummary>>("/api/queues", MySourceGenerationContext.Default.ListQueueSummary))!;
+        return (await http.GetFromJsonAsync<List<QueueSummary>>(Url("/queues"), MySourceGenerationContext.Default.ListQueueSummary))!;
+    }
+
+    public async Task<List<VhostSummary>> GetVhostsAsync()
+    {
+        return (await http.GetFromJsonAsync<List<VhostSummary>>($"/api/connections/{Connection}/vhosts", MySourceGenerationContext.Default.ListVhostSummary))!;
+    }
+
+    public async Task<List<ExchangeSummary>> GetExchangesAsync()
+    {
+        return (await http.GetFromJsonAsync<List<ExchangeSummary>>(Url("/exchanges"), MySourceGenerationContext.Default.ListExchangeSummary))!;
     }
 
-    public async Task<List<Message>> GetMessagesFromDbAsync(uint queueId)
+    public async Task<MessagesPage> GetMessagesPageFromDbAsync(uint queueId, int offset = 0, int? limit = null, string sort = "asc", string? search = null)
     {
-        return (await http.GetFromJsonAsync<List<Message>>($"/api/queues/{queueId}/messages", MySourceGenerationContext.Default.ListMessage))!;
+        var query = $"offset={offset}&sort={sort}";
+        if (limit != null)
+            query += $"&limit={limit}";
+        if (!string.IsNullOrEmpty(search))
+            query += $"&search={Uri.EscapeDataString(search)}";
+        return (await http.GetFromJsonAsync<MessagesPage>(Url($"/queues/{queueId}/messages?{query}"), MySourceGenerationContext.Default.MessagesPage))!;
     }
     
+    public async Task<List<SearchHit>> SearchAsync(string text)
+    {
+        return (await http.GetFromJsonAsync<List<SearchHit>>(Url($"/search?q={Uri.EscapeDataString(text)}"), MySourceGenerationContext.Default.ListSearchHit))!;
+    }
+
     public async Task<List<Message>> PeekRmqMessagesAsync(string queueName)
     {
-        return (await http.GetFromJsonAsync<List<Message>>($"/api/queue/peek?queue_name={queueName}", MySourceGenerationContext.Default.ListMessage))!;
+        return (await http.GetFromJsonAsync<List<Message>>(Url($"/queue/peek?queue_name={queueName}"), MySourceGenerationContext.Default.ListMessage))!;
     }
     
-    public async Task<LoadMessagesByQueueNameResponse> LoadMessagesToDbAsync(string queueName)
+    public async Task<LoadMessagesResult> LoadMessagesToDbAsync(string queueName)
+    {
+        using var response = await http.PostAsync(Url($"/queue/load?queue_name={queueName}"), null);
+        var job = await WaitForJobAsync(response);
+        return job.Result!.Value.Deserialize(MySourceGenerationContext.Default.LoadMessagesResult)!;
+    }
+
+    public async Task<List<JobInfo>> GetJobsAsync()
+    {
+        return (await http.GetFromJsonAsync<List<JobInfo>>("/api/jobs", MySourceGenerationContext.Default.ListJobInfo))!;
+    }
+
+    public async Task<JobInfo> ControlJobAsync(uint jobId, string action)
+    {
+        using var response = await http.PostAsync($"/api/jobs/{jobId}/{action}", null);
+        response.EnsureSuccessStatusCode();
+        return (await response.Content.ReadFromJsonAsync<JobInfo>(MySourceGenerationContext.Default.JobInfo))!;
+    }
+
+    async Task<JobInfo> WaitForJobAsync(HttpResponseMessage startJobResponse)
     {
-        using var response = await http.PostAsync($"/api/queue/load?queue_name={queueName}", null);
-        return (await response.Content.ReadFromJsonAsync<LoadMessagesByQueueNameResponse>(MySourceGenerationContext.Default.LoadMessagesByQueueNameResponse))!;
+        startJobResponse.EnsureSuccessStatusCode();
+        var job = (await startJobResponse.Content.ReadFromJsonAsync<JobInfo>(MySourceGenerationContext.Default.JobInfo))!;
+        
+        while (job.IsActive)
+        {
+            await Task.Delay(200);
+            job = (await http.GetFromJsonAsync<JobInfo>($"/api/jobs/{job.Id}", MySourceGenerationContext.Default.JobInfo))!;
+        }
+
+        return job.Status switch
+        {
+            "completed" => job,
+            "failed" => throw new InvalidOperationException($"{job.Description} failed: {job.Error}"),
+            _ => throw new OperationCanceledException($"{job.Description} was {job.Status}")
+        };
     }
 
     public async Task<EnvInfo> GetEnvInfoAsync()
@@ -51,7 +128,61 @@ public class Api(HttpClient http)
     public async Task SaveMessageAsync(uint queueId, uint messageId, string messagePayload)
     {
         using var body = new StringContent(messagePayload);
-        using var response = await http.PutAsync($"/api/queues/{queueId}/messages/{messageId}", body);
+        using var response = await http.PutAsync(Url($"/queues/{queueId}/messages/{messageId}"), body);
+        response.EnsureSuccessStatusCode();
+    }
+
+    public async Task SaveMessageMetadataAsync(uint queueId, uint messageId, Dictionary<string, JsonElement> properties, Dictionary<string, JsonElement> headers)
+    {
+        var body = new UpdateMessageMetadataRequest(properties, headers);
+        using var response = await http.PutAsJsonAsync(Url($"/queues/{queueId}/messages/{messageId}/metadata"), body, MySourceGenerationContext.Default.UpdateMessageMetadataRequest);
+        response.EnsureSuccessStatusCode();
+    }
+
+    public async Task<int> SetHeaderAsync(uint queueId, IEnumerable<uint> messageIds, string name, JsonElement? value)
+    {
+        var body = new SetHeaderRequest(messageIds, name, value);
+        using var response = await http.PostAsJsonAsync(Url($"/queues/{queueId}/messages/headers"), body, MySourceGenerationContext.Default.SetHeaderRequest);
+        response.EnsureSuccessStatusCode();
+        return (await response.Content.ReadFromJsonAsync<SetHeaderResult>(MySourceGenerationContext.Default.SetHeaderResult))!.Updated;
+    }
+
+    public async Task<TransformMessagesResult> TransformMessagesAsync(uint queueId, IEnumerable<uint> messageIds, JsonElement transformation, bool dryRun)
+    {
+        var body = new TransformMessagesRequest(messageIds, transformation, dryRun);
+        using var response = await http.PostAsJsonAsync(Url($"/queues/{queueId}/messages/transform"), body, MySourceGenerationContext.Default.TransformMessagesRequest);
+        response.EnsureSuccessStatusCode();
+        return (await response.Content.ReadFromJsonAsync<TransformMessagesResult>(MySourceGenerationContext.Default.TransformMessagesResult))!;
+    }
+
+    public async Task<string> ExportMessagesAsync(uint queueId)
+    {
+        return await http.GetStringAsync(Url($"/queues/{queueId}/export"));
+    }
+
+    public async Task<int> ImportMessagesAsync(uint queueId, string ndjson)
+    {
+        using var body = new StringContent(ndjson, System.Text.Encoding.UTF8, "application/x-ndjson");
+        using var response = await http.PostAsync(Url($"/queues/{queueId}/import"), body);
+        response.EnsureSuccessStatusCode();
+        return (await response.Content.ReadFromJsonAsync<ImportMessagesResult>(MySourceGenerationContext.Default.ImportMessagesResult))!.Imported;
+    }
+
+    public async Task<List<HistoryEntry>> GetMessageHistoryAsync(uint queueId, uint messageId)
+    {
+        return (await http.GetFromJsonAsync<List<HistoryEntry>>(Url($"/queues/{queueId}/messages/{messageId}/history"), MySourceGenerationContext.Default.ListHistoryEntry))!;
+    }
+
+    public async Task<List<HistoryEntry>> GetDeletedMessagesAsync(uint queueId)
+    {
+        return (await http.GetFromJsonAsync<List<HistoryEntry>>(Url($"/queues/{queueId}/deleted_messages"), MySourceGenerationContext.Default.ListHistoryEntry))!;
+    }
+
+    public async Task<int> UndoChangesAsync(uint queueId, IEnumerable<uint> historyIds)
+    {
+        var body = new UndoChangesRequest(historyIds);
+        using var response = await http.PostAsJsonAsync(Url($"/queues/{queueId}/history/undo"), body, MySourceGenerationContext.Default.UndoChangesRequest);
         response.EnsureSuccessStatusCode();
+        return (await response.Content.ReadFromJsonAsync<UndoChangesResult>(MySourceGenerationContext.Default.UndoChangesResult))!.Undone;
     }
-}
\ No newline at end of file
+}
@@ -3,22 +3,60 @@ using System.Text.Json.Serialization;
 
 namespace RmqToolsWeb;
 
-public record RmqConnectionInfo(string Domain, string? ServerName, string Vhost);
-public record EnvInfo(RmqConnectionInfo RmqConnectionInfo, int ImportanceLevel);
+public record RmqConnectionInfo(string Name, string Domain, string? ServerName, string Vhost, int ImportanceLevel);
+public record EnvInfo(List<RmqConnectionInfo> Connections);
 public record QueueSummary(uint? QueueId, string Name, bool Exclusive, int MessageCountInRmq, int MessageCountInDb);
-public record LoadMessagesByQueueNameResponse(uint QueueId, List<Message> Messages);
-public record Message(uint Id, string Payload, Dictionary<string, JsonElement> Headers);
+public record Message(uint Id, string Payload, string PayloadEncoding, Dictionary<string, JsonElement> Properties, Dictionary<string, JsonElement> Headers, string Exchange, string RoutingKey, bool Redelivered);
+public record MessagesPage(List<Message> Messages, int Total);
+public record SearchHit(uint QueueId, string QueueName, uint MessageId, string PayloadSnippet, string HeadersSnippet);
+public record HistoryEntry(uint HistoryId, string Change, long ChangedAt, bool Undone, Message Message);
+public record UndoChangesRequest(IEnumerable<uint> HistoryIds);
+public record UndoChangesResult(int Undone);
+public record UpdateMessageMetadataRequest(Dictionary<string, JsonElement> Properties, Dictionary<string, JsonElement> Headers);
+public record SetHeaderRequest(IEnumerable<uint> MessageIds, string Name, JsonElement? Value);
+public record SetHeaderResult(int Updated);
+public record TransformMessagesRequest(IEnumerable<uint> MessageIds, JsonElement Transformation, bool DryRun);
+public record TransformPreview(uint MessageId, string Diff);
+public record TransformFailure(uint MessageId, string Error);
+public record TransformMessagesResult(bool Applied, List<TransformPreview> Changed, int Unchanged, List<TransformFailure> Failed);
+public record ImportMessagesResult(int Imported);
 public record DeleteMessagesRequest(IEnumerable<uint> MessageIds);
-public record SendMessagesRequest(string DestinationQueueName, IEnumerable<uint> MessageIds, int? SendDelayMs = null);
+public record SendMessagesRequest(string DestinationQueueName, IEnumerable<uint> MessageIds, int? SendDelayMs = null, bool ToOriginalDestination = false, string? Exchange = null, string? RoutingKey = null, bool ToDeadLetterOrigin = false, string XDeath = "keep");
+public record SendMessagesResponse(List<uint> Routed, List<uint> Unrouted);
+public record ExchangeSummary(string Name, string ExchangeType);
+public record VhostSummary(string Name, string? Description);
 public record QueueCounters(string QueueName, int Messages);
+public record LoadMessagesResult(uint QueueId, int Loaded, int Returned);
+public record JobInfo(uint Id, string Kind, string Description, string Status, long Processed, long? Total, string? Error, JsonElement? Result)
+{
+    public bool IsActive => Status is "running" or "paused";
+}
+public record WebsocketMessage(string Type, JsonElement Data);
 
 
 [JsonSerializable(typeof(List<QueueSummary>))]
 [JsonSerializable(typeof(List<Message>))]
+[JsonSerializable(typeof(MessagesPage))]
+[JsonSerializable(typeof(List<SearchHit>))]
+[JsonSerializable(typeof(List<HistoryEntry>))]
+[JsonSerializable(typeof(UndoChangesRequest))]
+[JsonSerializable(typeof(UndoChangesResult))]
+[JsonSerializable(typeof(UpdateMessageMetadataRequest))]
+[JsonSerializable(typeof(SetHeaderRequest))]
+[JsonSerializable(typeof(SetHeaderResult))]
+[JsonSerializable(typeof(TransformMessagesRequest))]
+[JsonSerializable(typeof(TransformMessagesResult))]
+[JsonSerializable(typeof(ImportMessagesResult))]
 [JsonSerializable(typeof(List<QueueCounters>))]
+[JsonSerializable(typeof(List<ExchangeSummary>))]
+[JsonSerializable(typeof(List<VhostSummary>))]
 [JsonSerializable(typeof(EnvInfo))]
-[JsonSerializable(typeof(LoadMessagesByQueueNameResponse))]
 [JsonSerializable(typeof(DeleteMessagesRequest))]
 [JsonSerializable(typeof(SendMessagesRequest))]
+[JsonSerializable(typeof(SendMessagesResponse))]
+[JsonSerializable(typeof(LoadMessagesResult))]
+[JsonSerializable(typeof(JobInfo))]
+[JsonSerializable(typeof(List<JobInfo>))]
+[JsonSerializable(typeof(WebsocketMessage))]
 [JsonSourceGenerationOptions(PropertyNamingPolicy = JsonKnownNamingPolicy.SnakeCaseLower)]
 public partial class MySourceGenerationContext: JsonSerializerContext;
\ No newline at end of file
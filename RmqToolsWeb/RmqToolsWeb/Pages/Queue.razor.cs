@@ -9,6 +9,8 @@ namespace RmqToolsWeb.Pages;
 public partial class Queue : IAsyncDisposable
 {
     enum ShowMessageParts { Both, Headers, Payload }
+
+    static readonly int[] PageSizeOptions = [100, 1000, 10000];
     
     record MessageItem(int Index, uint MessageId, string CombinedString, List<string> HeaderLines, List<string> PayloadLines);
     
@@ -18,6 +20,7 @@ public partial class Queue : IAsyncDisposable
     [Inject] Api Api { get; set; } = null!;
     [Inject] IDialogService DialogService { get; set; } = null!;
     [Inject] WebsocketApi WebsocketApi { get; set; } = null!;
+    [Inject] ISnackbar Snackbar { get; set; } = null!;
 
     bool _loading = true;
     readonly List<MessageItem> _messages = [];
@@ -33,6 +36,11 @@ public partial class Queue : IAsyncDisposable
     bool? _readonlyMode;
     string _groupBySelector = "";
     MudDataGrid<MessageItem> _dataGrid = null!;
+    int _offset;
+    int _pageSize = PageSizeOptions[0];
+    int _numberOfMatchingMessages;
+    string _search = "";
+    bool _newestFirst;
 
     bool CanSendOrDeleteMessages => _queueId != null && _messages.Count != 0;
     bool GroupingEnabled => _groupBySelector != string.Empty;
@@ -59,9 +67,7 @@ public partial class Queue : IAsyncDisposable
                 if (requestedQueue.MessageCountInDb > 0)
                 {
                     _readonlyMode = false;
-                    var messages = await Api.GetMessagesFromDbAsync(_queueId.Value);
-                    CreateMessageItems(messages);
-                    _numberOfMessagesInDb = messages.Count;
+                    await FetchPage();
                 }
             }
             
@@ -96,13 +102,13 @@ public partial class Queue : IAsyncDisposable
         _loading = true;
         try
         {
-            var (queueId, messages) = await Api.LoadMessagesToDbAsync(QueueName);
-            _queueId = queueId;
-            CreateMessageItems(messages);
+            var result = await Api.LoadMessagesToDbAsync(QueueName);
+            _queueId = result.QueueId;
+            _offset = 0;
+            _readonlyMode = false;
+            await FetchPage();
             _numberOfRemoteMessages = 0;
             _numberOfRemoteMessagesIsOutOfDate = false;
-            _numberOfMessagesInDb = _messages.Count;
-            _readonlyMode = false;
         }
         finally
         {
@@ -110,6 +116,66 @@ public partial class Queue : IAsyncDisposable
         }
     }
 
+    // paging, sorting and searching happen on the server, so a big queue is never loaded into the browser at once
+    async Task FetchPage()
+    {
+        var sort = _newestFirst ? "desc" : "asc";
+        var page = await Api.GetMessagesPageFromDbAsync(_queueId!.Value, _offset, _pageSize, sort, _search);
+        if (page.Messages.Count == 0 && _offset > 0 && page.Total > 0)
+        {
+            // the page went away after messages were deleted or sent, show the last one instead
+            _offset = (page.Total - 1) / _pageSize * _pageSize;
+            page = await Api.GetMessagesPageFromDbAsync(_queueId!.Value, _offset, _pageSize, sort, _search);
+        }
+        else if (page.Total == 0)
+        {
+            _offset = 0;
+        }
+
+        _numberOfMatchingMessages = page.Total;
+        _numberOfMessagesInDb = string.IsNullOrEmpty(_search)
+            ? page.Total
+            : (await Api.GetMessagesPageFromDbAsync(_queueId!.Value, limit: 0)).Total;
+        _selectedMessages.Clear();
+        CreateMessageItems(page.Messages);
+    }
+
+    async Task ShowPage(Action change)
+    {
+        _loading = true;
+        try
+        {
+            change();
+            await FetchPage();
+        }
+        finally
+        {
+            _loading = false;
+        }
+    }
+
+    Task PreviousPage() => ShowPage(() => _offset = Math.Max(0, _offset - _pageSize));
+
+    Task NextPage() => ShowPage(() => _offset += _pageSize);
+
+    Task SetPageSize(int pageSize) => ShowPage(() =>
+    {
+        _pageSize = pageSize;
+        _offset = 0;
+    });
+
+    Task SetSearch(string? search) => ShowPage(() =>
+    {
+        _search = search ?? "";
+        _offset = 0;
+    });
+
+    Task ToggleSortOrder() => ShowPage(() =>
+    {
+        _newestFirst = !_newestFirst;
+        _offset = 0;
+    });
+
     void UnselectAll()
     {
         _selectedMessages.Clear();
@@ -122,11 +188,7 @@ public partial class Queue : IAsyncDisposable
         var messageIds = _selectedMessages.Select(x => x.MessageId);
         await Api.DeleteMessagesAsync(_queueId!.Value, messageIds);
 
-        ClearMessagesAfterOperation();
-
-        //update indexes
-        for (var i = 0; i < _messages.Count; i++)
-            _messages[i] = _messages[i] with { Index = i + 1 };
+        await FetchPage();
         
         _loading = false;
     }
@@ -136,16 +198,28 @@ public partial class Queue : IAsyncDisposable
         ShowLoadingForOperationOnMessages();
         
         var messageIds = _selectedMessages.Select(x => x.MessageId);
-        await Api.SendMessagesToQueueAsync(_queueId!.Value, messageIds, _moveToQueue);
+        var response = await Api.SendMessagesToQueueAsync(_queueId!.Value, messageIds, _moveToQueue);
 
         if (_moveToQueue == QueueName)
             _numberOfRemoteMessagesIsOutOfDate = true;
         
-        ClearMessagesAfterOperation();
+        await RefreshAfterSend(response);
         
         _loading = false;
     }
     
+    async Task ReplayMessagesToOrigin()
+    {
+        ShowLoadingForOperationOnMessages();
+
+        var messageIds = _selectedMessages.Select(x => x.MessageId);
+        var response = await Api.ReplayMessagesToOriginAsync(_queueId!.Value, messageIds);
+
+        await RefreshAfterSend(response);
+
+        _loading = false;
+    }
+
     async Task SendMessagesToQueueWithDelay()
     {
         var options = new DialogOptions
@@ -168,25 +242,23 @@ public partial class Queue : IAsyncDisposable
         var dialog = await DialogService.ShowAsync<SendWithDelayDialog>($"Send {messageCount} {MessageWord(messageCount)} with delay", parameters, options);
         var result = await dialog.Result;
 
-        if (!result.Canceled)
+        if (!result!.Canceled)
         {
             if (_moveToQueue == QueueName)
                 _numberOfRemoteMessagesIsOutOfDate = true;
         
-            ClearMessagesAfterOperation();
+            await RefreshAfterSend((SendMessagesResponse)result.Data!);
         }
     }
 
-    void ClearMessagesAfterOperation()
+    async Task RefreshAfterSend(SendMessagesResponse response)
     {
-        if (_selectedMessages.Count == 0)
-            _messages.Clear();
-        else
-            _messages.RemoveAll(x => _selectedMessages.Contains(x));
-        _numberOfMessagesInDb = _messages.Count;
-        _selectedMessages.Clear();
+        await FetchPage();
+
+        if (response.Unrouted.Count > 0)
+            Snackbar.Add($"{response.Unrouted.Count} {MessageWord(response.Unrouted.Count)} couldn't be routed and stayed in the database", Severity.Warning);
     }
-    
+
     string MessageWord(int count) => count == 1 ? "message" : "messages";
 
     void ShowLoadingForOperationOnMessages()
@@ -203,32 +275,31 @@ public partial class Queue : IAsyncDisposable
             .Index()
             .Select(x =>
             {
-                var headerLines = CreateHeaderLines(x.Item.Headers);
+                var headerLines = CreateHeaderLines(x.Item.Properties, x.Item.Headers);
                 var payloadLines = CreatePayloadLines(x.Item.Payload);
                 var combinedString = GetCombinedString(headerLines, payloadLines);
-                return new MessageItem(x.Index + 1, x.Item.Id, combinedString, headerLines, payloadLines);
+                return new MessageItem(_offset + x.Index + 1, x.Item.Id, combinedString, headerLines, payloadLines);
             })
             .OrderBy(x => x.Index));
     }
 
     List<string> CreatePayloadLines(string payload) => payload.Split('\n').Select(x => $"{x}\n").ToList();
 
-    List<string> CreateHeaderLines(Dictionary<string, JsonElement> headers)
+    List<string> CreateHeaderLines(Dictionary<string, JsonElement> properties, Dictionary<string, JsonElement> headers)
     {
-        var list = new List<string>(headers.Count);
-        foreach (var kvp in headers)
+        var list = new List<string>(properties.Count + headers.Count);
+        foreach (var kvp in properties)
         {
-            if (kvp.Value.ValueKind == JsonValueKind.Object)
-            {
-                list.Add($"{kvp.Key}:\n");
-                foreach (var prop in kvp.Value.EnumerateObject())
-                {
-                    list.Add($"    {prop.Name}: {prop.Value}\n");
-                }
-            }
-            else
-            {
+            if (kvp.Value.ValueKind != JsonValueKind.Null)
                 list.Add($"{kvp.Key}: {kvp.Value}\n");
+        }
+
+        if (headers.Count > 0)
+        {
+            list.Add("headers:\n");
+            foreach (var kvp in headers)
+            {
+                list.Add($"    {kvp.Key}: {kvp.Value}\n");
             }
         }
 
@@ -290,10 +361,9 @@ public partial class Queue : IAsyncDisposable
         await DialogService.ShowAsync<ExportFilteredLinesDialog>("Filtered lines", parameters, options);
     }
 
-    async Task EditMessage(int messageIndex)
+    async Task EditMessage(MessageItem message)
     {
-        var index = messageIndex - 1;
-        var message = _messages[index];
+        var index = _messages.IndexOf(message);
         var parameters = new DialogParameters<EditMessageDialog>
         {
             { x => x.QueueId, _queueId!.Value },
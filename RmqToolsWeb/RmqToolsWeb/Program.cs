@@ -26,8 +26,8 @@ builder.Services.AddSingleton(new Home.HomeGridFilters([]));
 
 var envUri = new Uri(builder.HostEnvironment.BaseAddress);
 var apiUrl = builder.HostEnvironment.IsDevelopment() ? "http://localhost:3000" : builder.HostEnvironment.BaseAddress;
-var wsUrl = builder.HostEnvironment.IsDevelopment() ? "ws://localhost:3000/api/ws" : $"ws://{envUri.Host}:{envUri.Port}/api/ws";
+var wsUrl = builder.HostEnvironment.IsDevelopment() ? "ws://localhost:3000" : $"ws://{envUri.Host}:{envUri.Port}";
 builder.Services.AddScoped(_ => new HttpClient { BaseAddress = new Uri(apiUrl) });
-builder.Services.AddTransient(_ => new WebsocketApi(new Uri(wsUrl)));
+builder.Services.AddTransient(sp => new WebsocketApi(wsUrl, sp.GetRequiredService<Api>()));
 
 await builder.Build().RunAsync();
\ No newline at end of file
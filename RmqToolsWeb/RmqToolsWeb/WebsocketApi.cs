@@ -3,13 +3,14 @@ using System.Text.Json;
 
 namespace RmqToolsWeb;
 
-public class WebsocketApi(Uri uri) : IAsyncDisposable
+public class WebsocketApi(string baseUrl, Api api) : IAsyncDisposable
 {
     readonly CancellationTokenSource _cts = new();
     readonly ClientWebSocket _webSocket = new();
     
-    public async Task StartAsync(Func<Dictionary<string, QueueCounters>, Task> onReceive)
+    public async Task StartAsync(Func<Dictionary<string, QueueCounters>, Task> onReceive, Func<JobInfo, Task>? onJob = null)
     {
+        var uri = new Uri(baseUrl + api.WebsocketPath());
         await _webSocket.ConnectAsync(uri, cancellationToken: _cts.Token);
         
         var bytes = new byte[10000];
@@ -18,10 +19,18 @@ public class WebsocketApi(Uri uri) : IAsyncDisposable
             while (!_cts.Token.IsCancellationRequested)
             {
                 (bytes, var length) = await ReadMessageBytesAsync(bytes);
-                var list = JsonSerializer.Deserialize<List<QueueCounters>>(bytes[.. length], MySourceGenerationContext.Default.ListQueueCounters);
-                var dict = list!.ToDictionary(x => x.QueueName);
-                
-                await onReceive(dict);
+                var message = JsonSerializer.Deserialize(bytes[.. length], MySourceGenerationContext.Default.WebsocketMessage)!;
+                switch (message.Type)
+                {
+                    case "queue_counters":
+                        var list = message.Data.Deserialize(MySourceGenerationContext.Default.ListQueueCounters);
+                        var dict = list!.ToDictionary(x => x.QueueName);
+                        await onReceive(dict);
+                        break;
+                    case "job" when onJob != null:
+                        await onJob(message.Data.Deserialize(MySourceGenerationContext.Default.JobInfo)!);
+                        break;
+                }
             }
         }, _cts.Token);
     }
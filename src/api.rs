@@ -1,79 +1,126 @@
 mod api_error;
+pub mod operations;
+mod vhosts;
 
 use crate::api::api_error::ApiError;
+use crate::api::operations::Destination;
+use crate::api::vhosts::{VhostData, Vhosts};
 use crate::database::{Database, MessageId, MessageSelector, QueueId};
+use crate::dead_letter;
 use crate::dtos::{
-    DeleteMessagesRequest, EnvInfo, LoadMessagesByQueueNameQuery, LoadMessagesByQueueNameResponse,
-    Message, PeekMessagesQuery, QueueSummary, RmqConnectionInfo, SendMessagesRequest,
+    DeleteMessagesRequest, EnvInfo, ExchangeSummary, HistoryEntry, ImportMessagesResult, JobInfo,
+    JobKind, LoadMessagesByQueueNameQuery, Message, MessagesPage, MessagesPageQuery,
+    MoveMessagesQuery, PeekMessagesQuery, PurgeHistoryResult, QueueSummary, RmqConnectionInfo,
+    SearchHit, SearchQuery, SendMessagesRequest, SetHeaderRequest, SetHeaderResult,
+    TransformFailure, TransformMessagesRequest, TransformMessagesResult, TransformPreview,
+    UndoChangesRequest, UndoChangesResult, UpdateMessageMetadataRequest, VhostQuery, VhostSummary,
+    WebsocketMessage,
 };
+use crate::jobs::{JobId, Jobs};
+use crate::message_filter::MessageFilter;
+use crate::message_metadata;
+use crate::ndjson;
 use crate::rabbitmq::Rabbitmq;
 use crate::rmq_background::RmqBackground;
+use crate::transform::{self, Transformation};
 use anyhow::Result;
 use axum::extract::ws::{WebSocket, WebSocketUpgrade};
-use axum::extract::{ConnectInfo, Path, Query, State};
-use axum::http::HeaderValue;
-use axum::response::Response;
+use axum::extract::DefaultBodyLimit;
+use axum::extract::{ConnectInfo, FromRequestParts, Path, Query, State};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum::routing::{any, delete, get, post, put};
 use axum::{Json, Router};
 use log::{debug, error, info};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::Mutex;
+use tokio::sync::broadcast::error::RecvError;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::services::{ServeDir, ServeFile};
 
-struct GuardedData {
-    rabbitmq: Arc<Rabbitmq>,
-    database: Database,
+const DEFAULT_SEARCH_LIMIT: u32 = 100;
+/// Message dumps are much bigger than other requests
+const IMPORT_BODY_LIMIT: usize = 1024 * 1024 * 1024;
+/// `delivery_mode` of messages that the broker writes to disk
+const PERSISTENT_DELIVERY_MODE: u8 = 2;
+
+pub struct GuardedData {
+    pub rabbitmq: Arc<Rabbitmq>,
+    pub database: Database,
+}
+
+/// A broker and vhost that the API works with. Every connection has its own local database
+/// and background counter updates. Other vhosts of the broker are opened on demand
+pub struct Connection {
+    pub name: String,
+    pub server_name: Option<String>,
+    pub importance_level: u8,
+    pub rabbitmq: Arc<Rabbitmq>,
+    pub database: Database,
+    pub rmq_background: RmqBackground,
+    pub update_interval: Duration,
 }
 
+/// State of the routes of one connection
 #[derive(Clone)]
 struct AppState {
-    guarded: Arc<Mutex<GuardedData>>,
+    vhosts: Vhosts,
+    jobs: Jobs,
+}
+
+/// Data of the vhost selected with the `vhost` query parameter
+struct SelectedVhost(VhostData);
+
+/// State of the routes shared by all connections
+#[derive(Clone)]
+struct ServerState {
     env_info: EnvInfo,
-    rmq_background: RmqBackground,
+    jobs: Jobs,
 }
 
 impl AppState {
-    fn new(
-        rabbitmq: Arc<Rabbitmq>,
-        rabbitmq_server_name: Option<String>,
-        importance_level: u8,
-        database: Database,
-        rmq_background: RmqBackground,
-    ) -> Self {
-        let rmq_connection_info = rabbitmq.get_connection_info();
+    fn new(connection: Connection, jobs: Jobs) -> Self {
         Self {
-            env_info: EnvInfo {
-                rmq_connection_info: RmqConnectionInfo {
-                    domain: rmq_connection_info.domain,
-                    server_name: rabbitmq_server_name,
-                    vhost: rmq_connection_info.vhost,
-                },
-                importance_level,
-            },
-            guarded: Arc::new(Mutex::new(GuardedData { rabbitmq, database })),
-            rmq_background,
+            vhosts: Vhosts::new(connection),
+            jobs,
         }
     }
 }
 
-pub fn build_api(
-    rabbitmq: Arc<Rabbitmq>,
-    rabbitmq_server_name: Option<String>,
-    importance_level: u8,
-    database: Database,
-    rmq_background: RmqBackground,
-    wwwroot_dir: std::path::PathBuf,
-) -> Router {
-    let state = AppState::new(
-        rabbitmq,
-        rabbitmq_server_name,
-        importance_level,
-        database,
-        rmq_background,
-    );
+impl FromRequestParts<AppState> for SelectedVhost {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, ApiError> {
+        let Query(query) = Query::<VhostQuery>::try_from_uri(&parts.uri)?;
+        Ok(SelectedVhost(
+            state.vhosts.get(query.vhost.as_deref()).await?,
+        ))
+    }
+}
+
+impl Connection {
+    fn info(&self) -> RmqConnectionInfo {
+        let rmq_connection_info = self.rabbitmq.get_connection_info();
+        RmqConnectionInfo {
+            name: self.name.clone(),
+            domain: rmq_connection_info.domain,
+            server_name: self.server_name.clone(),
+            vhost: rmq_connection_info.vhost,
+            importance_level: self.importance_level,
+        }
+    }
+}
+
+pub fn build_api(connections: Vec<Connection>, wwwroot_dir: std::path::PathBuf) -> Router {
+    let jobs = Jobs::new();
+    let server_state = ServerState {
+        env_info: EnvInfo {
+            connections: connections.iter().map(Connection::info).collect(),
+        },
+        jobs: jobs.clone(),
+    };
 
     let mut index_html_path = wwwroot_dir.clone();
     index_html_path.push("index.html");
@@ -84,34 +131,80 @@ pub fn build_api(
         .allow_headers(Any)
         .allow_origin("http://localhost:5009".parse::<HeaderValue>().unwrap());
 
+    let mut api = Router::new()
+        .route("/env_info", get(get_env_info))
+        .route("/jobs", get(list_jobs))
+        .route("/jobs/{job_id}", get(get_job))
+        .route("/jobs/{job_id}", delete(remove_job))
+        .route("/jobs/{job_id}/pause", post(pause_job))
+        .route("/jobs/{job_id}/resume", post(resume_job))
+        .route("/jobs/{job_id}/cancel", post(cancel_job))
+        .with_state(server_state);
+
+    // every connection gets its own copy of the routes, so that handlers don't need its name
+    for connection in connections {
+        let path = format!("/connections/{}", connection.name);
+        let state = AppState::new(connection, jobs.clone());
+        api = api.nest(&path, build_connection_api(state));
+    }
+
     Router::new()
-        .nest(
-            "/api",
-            Router::new()
-                .route("/env_info", get(get_env_info))
-                .route("/queue/load", post(load_messages_by_queue_name))
-                .route("/queue/peek", get(peek_messages))
-                .route("/queues", get(list_queues))
-                .route("/queues/{queue_id}/messages", get(get_messages))
-                .route("/queues/{queue_id}/messages", delete(delete_messages))
-                .route("/queues/{queue_id}/messages/send", post(send_messages))
-                .route(
-                    "/queues/{queue_id}/messages/{message_id}",
-                    put(update_message),
-                )
-                .route("/ws", any(ws_handler))
-                .with_state(state)
-                .layer(cors_layer),
-        )
+        .nest("/api", api.layer(cors_layer))
         .fallback_service(ServeDir::new(wwwroot_dir).fallback(ServeFile::new(index_html_path)))
 }
 
-async fn get_env_info(State(state): State<AppState>) -> Json<EnvInfo> {
+fn build_connection_api(state: AppState) -> Router {
+    Router::new()
+        .route("/queue/load", post(load_messages_by_queue_name))
+        .route("/queue/peek", get(peek_messages))
+        .route("/queue/move", post(move_messages))
+        .route("/queues", get(list_queues))
+        .route("/exchanges", get(list_exchanges))
+        .route("/vhosts", get(list_vhosts))
+        .route("/queues/{queue_id}/messages", get(get_messages))
+        .route("/queues/{queue_id}/messages", delete(delete_messages))
+        .route("/queues/{queue_id}/messages/send", post(send_messages))
+        .route(
+            "/queues/{queue_id}/messages/{message_id}",
+            put(update_message),
+        )
+        .route(
+            "/queues/{queue_id}/messages/{message_id}/metadata",
+            put(update_message_metadata),
+        )
+        .route("/queues/{queue_id}/messages/headers", post(set_header))
+        .route(
+            "/queues/{queue_id}/messages/transform",
+            post(transform_messages),
+        )
+        .route(
+            "/queues/{queue_id}/messages/{message_id}/history",
+            get(get_message_history),
+        )
+        .route(
+            "/queues/{queue_id}/deleted_messages",
+            get(get_deleted_messages),
+        )
+        .route("/queues/{queue_id}/history", delete(purge_history))
+        .route("/queues/{queue_id}/history/undo", post(undo_changes))
+        .route("/queues/{queue_id}/export", get(export_messages))
+        .route(
+            "/queues/{queue_id}/import",
+            post(import_messages).layer(DefaultBodyLimit::max(IMPORT_BODY_LIMIT)),
+        )
+        .route("/search", get(search_messages))
+        .route("/ws", any(ws_handler))
+        .with_state(state)
+}
+
+async fn get_env_info(State(state): State<ServerState>) -> Json<EnvInfo> {
     Json(state.env_info)
 }
 
-async fn list_queues(State(state): State<AppState>) -> Result<Json<Vec<QueueSummary>>, ApiError> {
-    let guarded = state.guarded.lock().await;
+async fn list_queues(
+    SelectedVhost(vhost): SelectedVhost,
+) -> Result<Json<Vec<QueueSummary>>, ApiError> {
+    let guarded = vhost.guarded.lock().await;
 
     let remote_queues = guarded.rabbitmq.list_queues().await?;
     let local_queues = guarded.database.get_queues()?;
@@ -133,149 +226,276 @@ async fn list_queues(State(state): State<AppState>) -> Result<Json<Vec<QueueSumm
     Ok(Json::from(queues))
 }
 
+async fn list_vhosts(State(state): State<AppState>) -> Result<Json<Vec<VhostSummary>>, ApiError> {
+    let vhosts = state
+        .vhosts
+        .rabbitmq()
+        .list_vhosts()
+        .await?
+        .into_iter()
+        .map(|x| VhostSummary {
+            name: x.name,
+            description: x.description,
+        })
+        .collect();
+
+    Ok(Json(vhosts))
+}
+
+async fn list_exchanges(
+    SelectedVhost(vhost): SelectedVhost,
+) -> Result<Json<Vec<ExchangeSummary>>, ApiError> {
+    let guarded = vhost.guarded.lock().await;
+
+    let exchanges = guarded
+        .rabbitmq
+        .list_exchanges()
+        .await?
+        .into_iter()
+        .map(|x| ExchangeSummary {
+            name: x.name,
+            exchange_type: x.exchange_type,
+        })
+        .collect();
+
+    Ok(Json(exchanges))
+}
+
 async fn get_messages(
-    State(state): State<AppState>,
+    SelectedVhost(vhost): SelectedVhost,
     Path(queue_id): Path<QueueId>,
-) -> Result<Json<Vec<Message>>, ApiError> {
-    let guarded = state.guarded.lock().await;
-    let messages = guarded
-        .database
-        .get_messages(&MessageSelector::AllInQueue(queue_id))?;
-    Ok(Json(messages))
+    Query(query): Query<MessagesPageQuery>,
+) -> Result<Json<MessagesPage>, ApiError> {
+    let guarded = vhost.guarded.lock().await;
+    let page = guarded.database.get_messages_page(queue_id, &query)?;
+    Ok(Json(page))
+}
+
+async fn search_messages(
+    SelectedVhost(vhost): SelectedVhost,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<Vec<SearchHit>>, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+    let guarded = vhost.guarded.lock().await;
+    let hits = guarded.database.search_messages(&query.q, limit)?;
+    Ok(Json(hits))
 }
 
 async fn send_messages(
     State(state): State<AppState>,
+    SelectedVhost(vhost): SelectedVhost,
     Path(queue_id): Path<QueueId>,
     Json(request): Json<SendMessagesRequest>,
-) -> Result<(), ApiError> {
-    let guarded = state.guarded.lock().await;
+) -> Result<(StatusCode, Json<JobInfo>), ApiError> {
+    let (queue_name, message_ids, destination) = {
+        let guarded = vhost.guarded.lock().await;
+        if request.confirmed && !guarded.rabbitmq.confirms_publishes() {
+            return Err(ApiError::ConfirmsUnavailable);
+        }
 
-    // get messages from database
-    let message_selector = match &request.message_ids[..] {
-        &[] => MessageSelector::AllInQueue(queue_id),
-        ids => MessageSelector::WithIds(ids),
-    };
-    let messages = guarded.database.get_messages(&message_selector)?;
-    let messages_len = messages.len();
-
-    // publish messages
-    for (i, message) in messages.into_iter().enumerate() {
-        if i > 0 {
-            if request.send_delay_ms > 0 {
-                tokio::time::sleep(Duration::from_millis(request.send_delay_ms)).await;
-            }
+        let queue_name = guarded
+            .database
+            .find_queue_name(queue_id)?
+            .ok_or(ApiError::QueueNotFound(queue_id))?;
+
+        let message_selector = match &request.message_ids[..] {
+            &[] => MessageSelector::AllInQueue(queue_id),
+            ids => MessageSelector::WithIds(ids),
+        };
+        let messages = guarded.database.get_messages(&message_selector)?;
+
+        // resolve destinations of all messages before publishing anything
+        let destination = Destination::from_request(&request)?;
+        for message in &messages {
+            destination.resolve(message)?;
+        }
 
-            if i % 1000 == 0 {
+        if request.confirmed {
+            let transient = messages
+                .iter()
+                .filter(|x| x.content.properties.delivery_mode != Some(PERSISTENT_DELIVERY_MODE))
+                .count();
+            if transient > 0 {
                 info!(
-                    "[progress] Sent {}/{} messages to queue {}",
-                    i, messages_len, request.destination_queue_name
+                    "{} of {} messages to send aren't persistent, they are published as persistent",
+                    transient,
+                    messages.len()
                 );
             }
         }
 
-        guarded
-            .rabbitmq
-            .send_message(
-                &request.destination_queue_name,
-                &message.payload,
-                message.headers,
-            )
-            .await?;
-    }
-
-    // delete messages
-    guarded.database.delete_messages(&message_selector)?;
+        let message_ids = match destination {
+            // replayed messages are published one origin queue after another
+            Destination::DeadLetterOrigin(_) => {
+                let groups = dead_letter::group_by_origin(&messages)?;
+                let summary = groups
+                    .iter()
+                    .map(|(queue, ids)| format!("{queue} ({})", ids.len()))
+                    .collect::<Vec<_>>();
+                info!("Replaying messages to queues {}", summary.join(", "));
+                groups.into_values().flatten().collect()
+            }
+            _ => messages.into_iter().map(|x| x.id).collect::<Vec<_>>(),
+        };
+        (queue_name, message_ids, destination)
+    };
 
-    info!(
-        "Sent all {} messages to queue {}",
-        messages_len, request.destination_queue_name
+    let description = format!(
+        "Send {} messages from queue {} to {}",
+        message_ids.len(),
+        queue_name,
+        destination
     );
+    let guarded = Arc::clone(&vhost.guarded);
+    let job = state.jobs.spawn(JobKind::Send, description, move |job| {
+        operations::send_messages(
+            guarded,
+            message_ids,
+            destination,
+            request.confirmed,
+            request.send_delay_ms,
+            job,
+        )
+    });
 
-    Ok(())
+    Ok((StatusCode::ACCEPTED, Json(job)))
 }
 
 async fn delete_messages(
     State(state): State<AppState>,
+    SelectedVhost(vhost): SelectedVhost,
     Path(queue_id): Path<QueueId>,
     Json(request): Json<DeleteMessagesRequest>,
-) -> Result<(), ApiError> {
-    let message_selector = match &request.message_ids[..] {
-        &[] => MessageSelector::AllInQueue(queue_id),
-        ids => MessageSelector::WithIds(ids),
-    };
-
-    let guarded = state.guarded.lock().await;
-    let deleted = guarded.database.delete_messages(&message_selector)?;
+) -> Result<(StatusCode, Json<JobInfo>), ApiError> {
+    let queue_name = vhost
+        .guarded
+        .lock()
+        .await
+        .database
+        .find_queue_name(queue_id)?
+        .ok_or(ApiError::QueueNotFound(queue_id))?;
 
-    info!("Deleted {} messages", deleted);
+    let description = match request.message_ids.len() {
+        0 => format!("Delete all messages of queue {queue_name}"),
+        n => format!("Delete {n} messages of queue {queue_name}"),
+    };
+    let guarded = Arc::clone(&vhost.guarded);
+    let job = state.jobs.spawn(JobKind::Delete, description, move |job| {
+        operations::delete_messages(guarded, queue_id, request.message_ids, job)
+    });
 
-    Ok(())
+    Ok((StatusCode::ACCEPTED, Json(job)))
 }
 
 async fn load_messages_by_queue_name(
     State(state): State<AppState>,
+    SelectedVhost(vhost): SelectedVhost,
     Query(query): Query<LoadMessagesByQueueNameQuery>,
-) -> Result<Json<LoadMessagesByQueueNameResponse>, ApiError> {
-    let guarded = state.guarded.lock().await;
-
-    let queue_id = match guarded.database.find_queue_by_name(&query.queue_name)? {
-        None => guarded.database.create_queue(&query.queue_name)?,
-        Some(queue_id) => queue_id,
+) -> Result<(StatusCode, Json<JobInfo>), ApiError> {
+    let filter = MessageFilter::from_criteria(&query.filter)?;
+    let description = match (&filter, query.max_count) {
+        (None, None) => format!("Load messages from queue {}", query.queue_name),
+        _ => format!("Load selected messages from queue {}", query.queue_name),
     };
+    let guarded = Arc::clone(&vhost.guarded);
+    let job = state.jobs.spawn(JobKind::Load, description, move |job| {
+        operations::load_messages(guarded, query.queue_name, query.max_count, filter, job)
+    });
 
-    let rmq_messages = guarded
-        .rabbitmq
-        .load_messages(&query.queue_name, false)
-        .await?
-        .into_iter()
-        .map(|x| (x.payload, x.properties.0))
-        .collect::<Vec<_>>();
-
-    if !rmq_messages.is_empty() {
-        guarded.database.save_messages(queue_id, &rmq_messages)?;
-        info!(
-            "Loaded {} messages to database from queue {}",
-            rmq_messages.len(),
-            query.queue_name
-        );
-    }
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
 
-    let messages = guarded
-        .database
-        .get_messages(&MessageSelector::AllInQueue(queue_id))?;
+async fn move_messages(
+    State(state): State<AppState>,
+    SelectedVhost(vhost): SelectedVhost,
+    Query(query): Query<MoveMessagesQuery>,
+) -> Result<(StatusCode, Json<JobInfo>), ApiError> {
+    let filter = MessageFilter::from_criteria(&query.filter)?;
+    let description = format!(
+        "Move messages from queue {} to queue {}",
+        query.source_queue_name, query.destination_queue_name
+    );
+    let rabbitmq = Arc::clone(&vhost.guarded.lock().await.rabbitmq);
+    let job = state.jobs.spawn(JobKind::Move, description, move |job| {
+        operations::move_messages(
+            rabbitmq,
+            query.source_queue_name,
+            query.destination_queue_name,
+            query.max_count,
+            filter,
+            job,
+        )
+    });
 
-    Ok(Json(LoadMessagesByQueueNameResponse { queue_id, messages }))
+    Ok((StatusCode::ACCEPTED, Json(job)))
 }
 
 async fn peek_messages(
-    State(state): State<AppState>,
+    SelectedVhost(vhost): SelectedVhost,
     Query(query): Query<PeekMessagesQuery>,
 ) -> Result<Json<Vec<Message>>, ApiError> {
-    let guarded = state.guarded.lock().await;
+    let guarded = vhost.guarded.lock().await;
 
     let rmq_messages = guarded
         .rabbitmq
-        .load_messages(&query.queue_name, true)
+        .peek_messages(&query.queue_name)
         .await?
         .into_iter()
         .enumerate()
         .map(|(i, msg)| Message {
             id: i as MessageId,
-            payload: msg.payload,
-            headers: msg.properties.0,
+            content: msg,
         })
         .collect::<Vec<_>>();
 
     Ok(Json(rmq_messages))
 }
 
+async fn list_jobs(State(state): State<ServerState>) -> Json<Vec<JobInfo>> {
+    Json(state.jobs.list())
+}
+
+async fn get_job(
+    State(state): State<ServerState>,
+    Path(job_id): Path<JobId>,
+) -> Result<Json<JobInfo>, ApiError> {
+    Ok(Json(state.jobs.get(job_id)?))
+}
+
+async fn remove_job(
+    State(state): State<ServerState>,
+    Path(job_id): Path<JobId>,
+) -> Result<Json<JobInfo>, ApiError> {
+    Ok(Json(state.jobs.remove(job_id)?))
+}
+
+async fn pause_job(
+    State(state): State<ServerState>,
+    Path(job_id): Path<JobId>,
+) -> Result<Json<JobInfo>, ApiError> {
+    Ok(Json(state.jobs.pause(job_id)?))
+}
+
+async fn resume_job(
+    State(state): State<ServerState>,
+    Path(job_id): Path<JobId>,
+) -> Result<Json<JobInfo>, ApiError> {
+    Ok(Json(state.jobs.resume(job_id)?))
+}
+
+async fn cancel_job(
+    State(state): State<ServerState>,
+    Path(job_id): Path<JobId>,
+) -> Result<Json<JobInfo>, ApiError> {
+    Ok(Json(state.jobs.cancel(job_id)?))
+}
+
 async fn update_message(
-    State(state): State<AppState>,
+    SelectedVhost(vhost): SelectedVhost,
     Path((queue_id, message_id)): Path<(QueueId, MessageId)>,
     payload: String,
 ) -> Result<(), ApiError> {
-    let guarded = state.guarded.lock().await;
+    let guarded = vhost.guarded.lock().await;
     let changed = guarded
         .database
         .set_message_payload(queue_id, message_id, &payload)?;
@@ -290,31 +510,249 @@ async fn update_message(
     }
 }
 
+async fn update_message_metadata(
+    SelectedVhost(vhost): SelectedVhost,
+    Path((queue_id, message_id)): Path<(QueueId, MessageId)>,
+    Json(request): Json<UpdateMessageMetadataRequest>,
+) -> Result<(), ApiError> {
+    let properties = message_metadata::parse_properties(request.properties)?;
+    message_metadata::validate_headers(&request.headers)?;
+
+    let guarded = vhost.guarded.lock().await;
+    let changed = guarded.database.set_message_metadata(
+        queue_id,
+        message_id,
+        &properties,
+        &request.headers,
+    )?;
+
+    match changed {
+        true => {
+            info!("Updated properties and headers of message {}", message_id);
+            Ok(())
+        }
+        false => Err(ApiError::MessageNotFound(message_id)),
+    }
+}
+
+async fn set_header(
+    SelectedVhost(vhost): SelectedVhost,
+    Path(queue_id): Path<QueueId>,
+    Json(request): Json<SetHeaderRequest>,
+) -> Result<Json<SetHeaderResult>, ApiError> {
+    message_metadata::validate_header_name(&request.name)?;
+    if let Some(value) = &request.value {
+        message_metadata::validate_header_value(value)?;
+    }
+
+    let message_selector = match &request.message_ids[..] {
+        &[] => MessageSelector::AllInQueue(queue_id),
+        ids => MessageSelector::WithIds(ids),
+    };
+
+    let guarded = vhost.guarded.lock().await;
+    let updated = guarded.database.set_header(
+        queue_id,
+        &message_selector,
+        &request.name,
+        request.value.as_ref(),
+    )?;
+
+    info!("Updated header '{}' of {} messages", request.name, updated);
+    Ok(Json(SetHeaderResult { updated }))
+}
+
+async fn transform_messages(
+    SelectedVhost(vhost): SelectedVhost,
+    Path(queue_id): Path<QueueId>,
+    Json(request): Json<TransformMessagesRequest>,
+) -> Result<Json<TransformMessagesResult>, ApiError> {
+    let transformation = Transformation::from_request(&request.transformation)?;
+
+    let message_selector = match &request.message_ids[..] {
+        &[] => MessageSelector::AllInQueue(queue_id),
+        ids => MessageSelector::WithIds(ids),
+    };
+
+    let guarded = vhost.guarded.lock().await;
+    let messages = guarded
+        .database
+        .get_queue_messages(queue_id, &message_selector)?;
+
+    let mut result = TransformMessagesResult {
+        applied: false,
+        changed: vec![],
+        unchanged: 0,
+        failed: vec![],
+    };
+    let mut payloads = vec![];
+    for message in messages {
+        match transformation.apply(&message.content) {
+            Ok(payload) if payload == message.content.payload => result.unchanged += 1,
+            Ok(payload) => {
+                result.changed.push(TransformPreview {
+                    message_id: message.id,
+                    diff: transform::payload_diff(&message.content.payload, &payload),
+                });
+                payloads.push((message.id, payload));
+            }
+            Err(error) => result.failed.push(TransformFailure {
+                message_id: message.id,
+                error,
+            }),
+        }
+    }
+
+    // nothing is changed unless every message can be transformed
+    if !request.dry_run && result.failed.is_empty() {
+        guarded.database.set_message_payloads(queue_id, &payloads)?;
+        result.applied = true;
+        info!("Transformed {} messages", payloads.len());
+    }
+
+    Ok(Json(result))
+}
+
+async fn export_messages(
+    SelectedVhost(vhost): SelectedVhost,
+    Path(queue_id): Path<QueueId>,
+) -> Result<impl IntoResponse, ApiError> {
+    let guarded = vhost.guarded.lock().await;
+    let queue_name = guarded
+        .database
+        .find_queue_name(queue_id)?
+        .ok_or(ApiError::QueueNotFound(queue_id))?;
+    let messages = guarded
+        .database
+        .get_messages(&MessageSelector::AllInQueue(queue_id))?;
+
+    info!(
+        "Exported {} messages of queue {}",
+        messages.len(),
+        queue_name
+    );
+
+    let content_disposition = format!("attachment; filename=\"{queue_name}.ndjson\"");
+    Ok((
+        [
+            (header::CONTENT_TYPE, ndjson::CONTENT_TYPE.to_string()),
+            (header::CONTENT_DISPOSITION, content_disposition),
+        ],
+        ndjson::export_messages(messages.iter().map(|x| &x.content)),
+    ))
+}
+
+async fn import_messages(
+    SelectedVhost(vhost): SelectedVhost,
+    Path(queue_id): Path<QueueId>,
+    body: String,
+) -> Result<Json<ImportMessagesResult>, ApiError> {
+    let messages = ndjson::import_messages(&body)?;
+
+    let guarded = vhost.guarded.lock().await;
+    let queue_name = guarded
+        .database
+        .find_queue_name(queue_id)?
+        .ok_or(ApiError::QueueNotFound(queue_id))?;
+    guarded.database.save_messages(queue_id, &messages)?;
+
+    info!(
+        "Imported {} messages to queue {}",
+        messages.len(),
+        queue_name
+    );
+    Ok(Json(ImportMessagesResult {
+        imported: messages.len(),
+    }))
+}
+
+async fn get_message_history(
+    SelectedVhost(vhost): SelectedVhost,
+    Path((queue_id, message_id)): Path<(QueueId, MessageId)>,
+) -> Result<Json<Vec<HistoryEntry>>, ApiError> {
+    let guarded = vhost.guarded.lock().await;
+    let history = guarded.database.get_message_history(queue_id, message_id)?;
+    Ok(Json(history))
+}
+
+async fn get_deleted_messages(
+    SelectedVhost(vhost): SelectedVhost,
+    Path(queue_id): Path<QueueId>,
+) -> Result<Json<Vec<HistoryEntry>>, ApiError> {
+    let guarded = vhost.guarded.lock().await;
+    let deleted = guarded.database.get_deleted_messages(queue_id)?;
+    Ok(Json(deleted))
+}
+
+async fn undo_changes(
+    SelectedVhost(vhost): SelectedVhost,
+    Path(queue_id): Path<QueueId>,
+    Json(request): Json<UndoChangesRequest>,
+) -> Result<Json<UndoChangesResult>, ApiError> {
+    let guarded = vhost.guarded.lock().await;
+    let undone = guarded
+        .database
+        .undo_changes(queue_id, &request.history_ids)?;
+    info!("Undone {} changes", undone);
+    Ok(Json(UndoChangesResult { undone }))
+}
+
+async fn purge_history(
+    SelectedVhost(vhost): SelectedVhost,
+    Path(queue_id): Path<QueueId>,
+) -> Result<Json<PurgeHistoryResult>, ApiError> {
+    let guarded = vhost.guarded.lock().await;
+    let purged = guarded.database.purge_history(queue_id)?;
+    info!("Purged {} history entries of queue {}", purged, queue_id);
+    Ok(Json(PurgeHistoryResult { purged }))
+}
+
 async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
+    SelectedVhost(vhost): SelectedVhost,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
 ) -> Response {
-    return ws.on_upgrade(move |socket| handle_socket(socket, state, addr));
+    return ws
+        .on_upgrade(move |socket| handle_socket(socket, vhost.rmq_background, state.jobs, addr));
 
-    async fn handle_socket(mut socket: WebSocket, app_state: AppState, addr: SocketAddr) {
+    async fn handle_socket(
+        mut socket: WebSocket,
+        rmq_background: RmqBackground,
+        jobs: Jobs,
+        addr: SocketAddr,
+    ) {
         debug!("Connected to websocket server from {}", addr);
 
-        let mut receiver = app_state.rmq_background.subscribe();
+        let mut counters_receiver = rmq_background.subscribe();
+        let mut jobs_receiver = jobs.subscribe();
 
         loop {
-            if receiver.changed().await.is_err() {
-                error!("tokio channel closed");
-                break;
-            }
+            let json = tokio::select! {
+                changed = counters_receiver.changed() => {
+                    if changed.is_err() {
+                        error!("tokio channel closed");
+                        break;
+                    }
+
+                    let counters = &*counters_receiver.borrow_and_update();
+                    serde_json::to_string(&WebsocketMessage::QueueCounters(counters)).unwrap()
+                }
+                job = jobs_receiver.recv() => match job {
+                    Ok(job) => serde_json::to_string(&WebsocketMessage::Job(&job)).unwrap(),
+                    Err(RecvError::Lagged(skipped)) => {
+                        debug!("Websocket {} skipped {} job updates", addr, skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => {
+                        error!("tokio channel closed");
+                        break;
+                    }
+                },
+            };
 
             debug!("About to push data to websocket {}", addr);
 
-            let json = {
-                let counters = &*receiver.borrow_and_update();
-                serde_json::to_string(counters).unwrap()
-            };
-
             if socket
                 .send(axum::extract::ws::Message::Text(json.into()))
                 .await
@@ -1,5 +1,13 @@
-use crate::database::{DatabaseError, MessageId};
+use crate::database::{DatabaseError, MessageId, QueueId};
+use crate::dead_letter::DeadLetterError;
+use crate::jobs::{JobCancelled, JobError};
+use crate::message_filter::FilterError;
+use crate::message_metadata::MetadataError;
+use crate::ndjson::ImportError;
 use crate::rabbitmq::RabbitMQError;
+use crate::routing_template::TemplateError;
+use crate::transform::TransformError;
+use axum::extract::rejection::QueryRejection;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use log::error;
@@ -15,12 +23,64 @@ pub enum ApiError {
 
     #[error("Message not found: {}", .0)]
     MessageNotFound(MessageId),
+
+    #[error("Queue not found: {}", .0)]
+    QueueNotFound(QueueId),
+
+    #[error("Vhost not found: {}", .0)]
+    VhostNotFound(String),
+
+    #[error(
+        "Confirmed sending needs an AMQP connection, set --amqp-url or amqp_url of the profile"
+    )]
+    ConfirmsUnavailable,
+
+    #[error("{}", .0)]
+    Query(#[from] QueryRejection),
+
+    #[error("{}", .0)]
+    Template(#[from] TemplateError),
+
+    #[error("{}", .0)]
+    DeadLetter(#[from] DeadLetterError),
+
+    #[error("{}", .0)]
+    Filter(#[from] FilterError),
+
+    #[error("{}", .0)]
+    Metadata(#[from] MetadataError),
+
+    #[error("{}", .0)]
+    Transform(#[from] TransformError),
+
+    #[error("{}", .0)]
+    Import(#[from] ImportError),
+
+    #[error("{}", .0)]
+    Job(#[from] JobError),
+
+    #[error("{}", .0)]
+    JobCancelled(#[from] JobCancelled),
 }
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
         let status_code = match self {
             ApiError::MessageNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::QueueNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::VhostNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::ConfirmsUnavailable => StatusCode::BAD_REQUEST,
+            ApiError::Query(_) => StatusCode::BAD_REQUEST,
+            ApiError::Template(_) => StatusCode::BAD_REQUEST,
+            ApiError::DeadLetter(_) => StatusCode::BAD_REQUEST,
+            ApiError::Filter(_) => StatusCode::BAD_REQUEST,
+            ApiError::Metadata(_) => StatusCode::BAD_REQUEST,
+            ApiError::Transform(_) => StatusCode::BAD_REQUEST,
+            ApiError::Import(_) => StatusCode::BAD_REQUEST,
+            ApiError::Database(DatabaseError::HistoryNotFound(_)) => StatusCode::NOT_FOUND,
+            ApiError::Database(DatabaseError::CannotUndo(..)) => StatusCode::CONFLICT,
+            ApiError::Job(JobError::NotFound(_)) => StatusCode::NOT_FOUND,
+            ApiError::Job(JobError::InvalidState(..)) => StatusCode::CONFLICT,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
@@ -0,0 +1,700 @@
+use crate::api::api_error::ApiError;
+use crate::api::{GuardedData, PERSISTENT_DELIVERY_MODE};
+use crate::database::{MessageId, MessageSelector, QueueId};
+use crate::dead_letter;
+use crate::dtos::{
+    DeleteMessagesResult, LoadMessagesResult, Message, MessageContent, MoveMessagesResult,
+    PublishMessagesResult, SendMessagesRequest, SendMessagesResponse, XDeathMode,
+};
+use crate::jobs::JobHandle;
+use crate::message_filter::MessageFilter;
+use crate::rabbitmq::{RabbitMQError, Rabbitmq};
+use crate::routing_template::{RoutingTemplate, TemplateError};
+use crate::transport::TakenMessage;
+use anyhow::anyhow;
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+const DELETE_CHUNK_SIZE: usize = 1000;
+const LOAD_BATCH_SIZE: u64 = 500;
+
+// Operations below run as background jobs. They lock the shared data only for short steps
+// so that the API stays responsive while they run
+
+/// Moves messages from the queue to the database.
+///
+/// With a filter all messages that are in the queue are taken until `max_count` of them match.
+/// The ones that don't match stay unacknowledged and are returned to the queue at the end.
+/// Over AMQP they get back to their original positions with their exchange and routing key,
+/// only marked as redelivered. Through the management API they are published back through
+/// the default exchange behind the messages that are in the queue.
+///
+/// Every batch is acknowledged only after it's saved, so if saving fails the batch is returned
+/// to the queue. Over AMQP the broker also returns it if the process stops in between,
+/// through the management API the batch is lost in that case
+pub async fn load_messages(
+    guarded: Arc<Mutex<GuardedData>>,
+    queue_name: String,
+    max_count: Option<u64>,
+    filter: Option<MessageFilter>,
+    mut job: JobHandle,
+) -> Result<Value, ApiError> {
+    job.checkpoint().await?;
+
+    let (queue_id, rabbitmq) = {
+        let guarded = guarded.lock().await;
+        let queue_id = match guarded.database.find_queue_by_name(&queue_name)? {
+            None => guarded.database.create_queue(&queue_name)?,
+            Some(queue_id) => queue_id,
+        };
+        (queue_id, Arc::clone(&guarded.rabbitmq))
+    };
+
+    warn_if_unacked_messages_are_lost(&rabbitmq, &queue_name);
+
+    // only the messages that are in the queue now are loaded, even if new ones keep coming
+    let mut total = rabbitmq.count_messages(&queue_name).await?;
+    let max_count = max_count.unwrap_or(u64::MAX);
+    if filter.is_none() {
+        total = total.min(max_count);
+    }
+    job.set_total(total);
+
+    let mut taker = rabbitmq.take_messages(&queue_name).await?;
+    let mut taken = 0;
+    let mut loaded = 0;
+    let result = async {
+        while taken < total && loaded < max_count {
+            job.checkpoint().await?;
+
+            let count = (total - taken).min(LOAD_BATCH_SIZE);
+            let messages = taker.take(count as u32).await?;
+
+            // the queue was drained by somebody else
+            if messages.is_empty() {
+                break;
+            }
+            taken += messages.len() as u64;
+
+            let to_save = select_messages(messages, filter.as_ref(), max_count - loaded);
+            let contents = to_save
+                .iter()
+                .map(|x| x.content.clone())
+                .collect::<Vec<_>>();
+
+            // every batch is saved in its own transaction and acknowledged after that
+            guarded
+                .lock()
+                .await
+                .database
+                .save_messages(queue_id, &contents)?;
+            taker.ack(&tags(&to_save)).await?;
+            loaded += to_save.len() as u64;
+
+            job.set_processed(taken);
+        }
+        Ok::<_, ApiError>(())
+    }
+    .await;
+
+    // returns the messages that weren't loaded, and the batch that failed to be saved.
+    // That batch may be partly duplicated if it was saved but not acknowledged,
+    // but none of it is lost
+    taker.release().await?;
+    result?;
+
+    let returned = taken - loaded;
+    info!(
+        "Loaded {} messages to database from queue {}, returned {} to the queue",
+        loaded, queue_name, returned
+    );
+
+    Ok(job_result(LoadMessagesResult {
+        queue_id,
+        loaded,
+        returned,
+    }))
+}
+
+/// Moves messages from one queue to another on the broker, without storing them.
+/// Messages that aren't moved are returned to the source queue as in `load_messages`.
+///
+/// Every batch is acknowledged only after it's published, so if publishing fails the batch
+/// is returned to the source queue
+pub async fn move_messages(
+    rabbitmq: Arc<Rabbitmq>,
+    source_queue: String,
+    destination_queue: String,
+    max_count: Option<u64>,
+    filter: Option<MessageFilter>,
+    mut job: JobHandle,
+) -> Result<Value, ApiError> {
+    warn_if_unacked_messages_are_lost(&rabbitmq, &source_queue);
+
+    let mut total = rabbitmq.count_messages(&source_queue).await?;
+    let max_count = max_count.unwrap_or(u64::MAX);
+    if filter.is_none() {
+        total = total.min(max_count);
+    }
+    job.set_total(total);
+
+    let mut taker = rabbitmq.take_messages(&source_queue).await?;
+    let mut taken = 0;
+    let mut moved = 0;
+    let result = async {
+        while taken < total && moved < max_count {
+            job.checkpoint().await?;
+
+            let count = (total - taken).min(LOAD_BATCH_SIZE);
+            let messages = taker.take(count as u32).await?;
+            if messages.is_empty() {
+                break;
+            }
+            taken += messages.len() as u64;
+
+            let to_move = select_messages(messages, filter.as_ref(), max_count - moved);
+            let contents = to_move
+                .iter()
+                .map(|x| x.content.clone())
+                .collect::<Vec<_>>();
+
+            publish_to_queue(&rabbitmq, &destination_queue, &contents).await?;
+            taker.ack(&tags(&to_move)).await?;
+            moved += to_move.len() as u64;
+
+            job.set_processed(taken);
+        }
+        Ok::<_, ApiError>(())
+    }
+    .await;
+
+    taker.release().await?;
+    result?;
+
+    let returned = taken - moved;
+    info!(
+        "Moved {} messages from queue {} to queue {}, returned {} to the source queue",
+        moved, source_queue, destination_queue, returned
+    );
+
+    Ok(job_result(MoveMessagesResult { moved, returned }))
+}
+
+/// Publishes messages that aren't stored in the database, e.g. read from a file
+pub async fn publish_messages(
+    rabbitmq: Arc<Rabbitmq>,
+    queue_name: String,
+    messages: Vec<MessageContent>,
+    mut job: JobHandle,
+) -> Result<Value, ApiError> {
+    job.set_total(messages.len() as u64);
+
+    let mut published = 0;
+    for chunk in messages.chunks(LOAD_BATCH_SIZE as usize) {
+        job.checkpoint().await?;
+        publish_to_queue(&rabbitmq, &queue_name, chunk).await?;
+        published += chunk.len() as u64;
+        job.set_processed(published);
+    }
+
+    info!("Published {} messages to queue {}", published, queue_name);
+
+    Ok(job_result(PublishMessagesResult { published }))
+}
+
+/// Publishes the messages and removes the routed ones from the database.
+///
+/// In the confirmed mode every message is published as persistent, because the broker
+/// confirms transient messages without writing them to disk
+pub async fn send_messages(
+    guarded: Arc<Mutex<GuardedData>>,
+    message_ids: Vec<MessageId>,
+    destination: Destination,
+    confirmed: bool,
+    send_delay_ms: u64,
+    mut job: JobHandle,
+) -> Result<Value, ApiError> {
+    if confirmed && !guarded.lock().await.rabbitmq.confirms_publishes() {
+        return Err(ApiError::ConfirmsUnavailable);
+    }
+
+    let messages_len = message_ids.len();
+    job.set_total(messages_len as u64);
+
+    let mut response = SendMessagesResponse {
+        routed: vec![],
+        unrouted: vec![],
+    };
+
+    for (i, message_id) in message_ids.into_iter().enumerate() {
+        if i > 0 {
+            if send_delay_ms > 0 {
+                tokio::time::sleep(Duration::from_millis(send_delay_ms)).await;
+            }
+
+            if i % 1000 == 0 {
+                info!(
+                    "[progress] Sent {}/{} messages to {}",
+                    i, messages_len, destination
+                );
+            }
+        }
+        job.checkpoint().await?;
+
+        let guarded = guarded.lock().await;
+
+        // the message could have been deleted while the job was running
+        let Some(mut message) = guarded
+            .database
+            .get_messages(&MessageSelector::WithIds(&[message_id]))?
+            .pop()
+        else {
+            job.set_processed(i as u64 + 1);
+            continue;
+        };
+
+        let (exchange, routing_key) = destination.resolve(&message)?;
+        destination.prepare(&mut message.content);
+        if confirmed {
+            message.content.properties.delivery_mode = Some(PERSISTENT_DELIVERY_MODE);
+        }
+        let routed = guarded
+            .rabbitmq
+            .send_message(&exchange, &routing_key, &message.content)
+            .await?;
+
+        match routed {
+            true => {
+                // delete right away so that a failed or interrupted send can be repeated
+                // without publishing the same message twice. Over AMQP the broker has
+                // confirmed the message at this point
+                guarded.database.delete_sent_message(message.id)?;
+                response.routed.push(message.id);
+            }
+            false => {
+                warn!(
+                    "Message {} wasn't routed: exchange '{}', routing key '{}'",
+                    message.id, exchange, routing_key
+                );
+                response.unrouted.push(message.id);
+            }
+        }
+        job.set_processed(i as u64 + 1);
+    }
+
+    info!(
+        "Sent {} of {} messages to {}, {} unrouted",
+        response.routed.len(),
+        messages_len,
+        destination,
+        response.unrouted.len()
+    );
+
+    Ok(job_result(response))
+}
+
+pub async fn delete_messages(
+    guarded: Arc<Mutex<GuardedData>>,
+    queue_id: QueueId,
+    message_ids: Vec<MessageId>,
+    mut job: JobHandle,
+) -> Result<Value, ApiError> {
+    let mut deleted = 0;
+
+    if message_ids.is_empty() {
+        job.checkpoint().await?;
+        deleted = guarded
+            .lock()
+            .await
+            .database
+            .delete_messages(&MessageSelector::AllInQueue(queue_id))?;
+    } else {
+        job.set_total(message_ids.len() as u64);
+
+        for (i, chunk) in message_ids.chunks(DELETE_CHUNK_SIZE).enumerate() {
+            job.checkpoint().await?;
+            deleted += guarded
+                .lock()
+                .await
+                .database
+                .delete_messages(&MessageSelector::WithIds(chunk))?;
+            job.set_processed((i * DELETE_CHUNK_SIZE + chunk.len()) as u64);
+        }
+    }
+
+    info!("Deleted {} messages", deleted);
+
+    Ok(job_result(DeleteMessagesResult { deleted }))
+}
+
+/// Returns the messages selected by the filter, up to `max_count`
+fn select_messages(
+    messages: Vec<TakenMessage>,
+    filter: Option<&MessageFilter>,
+    max_count: u64,
+) -> Vec<TakenMessage> {
+    messages
+        .into_iter()
+        .filter(|x| filter.is_none_or(|f| f.matches(&x.content)))
+        .take(max_count.try_into().unwrap_or(usize::MAX))
+        .collect()
+}
+
+fn tags(messages: &[TakenMessage]) -> Vec<u64> {
+    messages.iter().map(|x| x.tag).collect()
+}
+
+/// Taking messages through the management API removes them from the queue right away,
+/// so the batch in progress is lost if the tool stops before it's stored or published
+fn warn_if_unacked_messages_are_lost(rabbitmq: &Rabbitmq, queue_name: &str) {
+    if !rabbitmq.keeps_unacked_messages() {
+        warn!(
+            "Taking messages from queue {} through the management API, a batch in progress \
+            is lost if the tool stops. Set --amqp-url to keep it in the queue until it's stored",
+            queue_name
+        );
+    }
+}
+
+/// Publishes the messages through the default exchange, which routes them to the queue
+async fn publish_to_queue(
+    rabbitmq: &Rabbitmq,
+    queue_name: &str,
+    messages: &[MessageContent],
+) -> Result<(), ApiError> {
+    for message in messages {
+        if !rabbitmq.send_message("", queue_name, message).await? {
+            return Err(RabbitMQError::Other(anyhow!(
+                "Couldn't publish a message to queue {queue_name}, it wasn't routed"
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+fn job_result(result: impl Serialize) -> Value {
+    serde_json::to_value(result).expect("Job result is always serializable")
+}
+
+/// Where `send_messages` publishes messages to
+pub enum Destination {
+    Queue(String),
+    /// The queue every message was dead-lettered from
+    DeadLetterOrigin(XDeathMode),
+    Exchange {
+        exchange: RoutingTemplate,
+        routing_key: RoutingTemplate,
+        description: String,
+    },
+}
+
+impl Destination {
+    pub fn from_request(request: &SendMessagesRequest) -> Result<Self, TemplateError> {
+        if request.to_original_destination {
+            return Ok(Destination::Exchange {
+                exchange: RoutingTemplate::parse("{exchange}")?,
+                routing_key: RoutingTemplate::parse("{routing_key}")?,
+                description: "original destinations".to_string(),
+            });
+        }
+
+        if request.to_dead_letter_origin {
+            return Ok(Destination::DeadLetterOrigin(request.x_death));
+        }
+
+        let destination = match &request.exchange {
+            None => Destination::Queue(request.destination_queue_name.clone()),
+            Some(exchange) => {
+                let routing_key = request.routing_key.as_deref().unwrap_or_default();
+                Destination::Exchange {
+                    exchange: RoutingTemplate::parse(exchange)?,
+                    routing_key: RoutingTemplate::parse(routing_key)?,
+                    description: format!("exchange {exchange} with routing key {routing_key}"),
+                }
+            }
+        };
+        Ok(destination)
+    }
+
+    /// Returns the exchange and routing key to publish the message with
+    pub fn resolve(&self, message: &Message) -> Result<(String, String), ApiError> {
+        match self {
+            Destination::Queue(queue) => Ok((String::new(), queue.clone())),
+            Destination::DeadLetterOrigin(_) => {
+                Ok((String::new(), dead_letter::origin_queue(message)?))
+            }
+            Destination::Exchange {
+                exchange,
+                routing_key,
+                ..
+            } => Ok((exchange.render(message)?, routing_key.render(message)?)),
+        }
+    }
+
+    /// Changes the message before it's published
+    pub fn prepare(&self, content: &mut MessageContent) {
+        if let Destination::DeadLetterOrigin(x_death) = self {
+            x_death.apply(&mut content.headers);
+        }
+    }
+}
+
+impl Display for Destination {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Destination::Queue(queue) => write!(f, "queue {queue}"),
+            Destination::DeadLetterOrigin(_) => write!(f, "dead-letter origin queues"),
+            Destination::Exchange { description, .. } => write!(f, "{description}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtos::{FilterCriteria, JobKind};
+    use crate::jobs::Jobs;
+    use crate::transport::fake::FakeTransport;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn message(payload: &str) -> MessageContent {
+        serde_json::from_value(json!({ "payload": payload })).unwrap()
+    }
+
+    async fn guarded_data(transport: &FakeTransport) -> (TempDir, Arc<Mutex<GuardedData>>) {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test");
+        let endpoint = transport.serve_management_api().await;
+        let guarded = GuardedData {
+            rabbitmq: Arc::new(Rabbitmq::with_transport(
+                endpoint,
+                Box::new(transport.clone()),
+            )),
+            database: crate::database::Database::new(path.to_str().unwrap(), "/").unwrap(),
+        };
+        (dir, Arc::new(Mutex::new(guarded)))
+    }
+
+    /// Runs the operation as a job and returns its error, if any
+    async fn run<F, Fut>(f: F) -> Option<String>
+    where
+        F: FnOnce(JobHandle) -> Fut,
+        Fut: Future<Output = Result<Value, ApiError>> + Send + 'static,
+    {
+        let jobs = Jobs::new();
+        let mut updates = jobs.subscribe();
+        jobs.spawn(JobKind::Send, "test".to_string(), f);
+        loop {
+            let info = updates.recv().await.unwrap();
+            if !info.status.is_active() {
+                return info.error;
+            }
+        }
+    }
+
+    async fn send(transport: &FakeTransport, confirmed: bool) -> Option<u8> {
+        let (_dir, guarded) = guarded_data(transport).await;
+        let message_ids = {
+            let guarded = guarded.lock().await;
+            let queue_id = guarded.database.create_queue("q").unwrap();
+            guarded
+                .database
+                .save_messages(queue_id, &[message("transient")])
+                .unwrap();
+            let messages = guarded
+                .database
+                .get_messages(&MessageSelector::AllInQueue(queue_id))
+                .unwrap();
+            messages.into_iter().map(|x| x.id).collect()
+        };
+
+        let destination = Destination::Queue("target".to_string());
+        let error =
+            run(move |job| send_messages(guarded, message_ids, destination, confirmed, 0, job))
+                .await;
+        assert_eq!(error, None);
+
+        let published = transport.published.lock().unwrap();
+        published[0].2.properties.delivery_mode
+    }
+
+    #[tokio::test]
+    async fn confirmed_send_publishes_persistent_messages() {
+        let transport = FakeTransport {
+            amqp: true,
+            ..Default::default()
+        };
+
+        assert_eq!(send(&transport, true).await, Some(PERSISTENT_DELIVERY_MODE));
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_send_keeps_delivery_mode() {
+        let transport = FakeTransport {
+            amqp: true,
+            ..Default::default()
+        };
+
+        assert_eq!(send(&transport, false).await, None);
+    }
+
+    #[tokio::test]
+    async fn confirmed_send_needs_confirms() {
+        let transport = FakeTransport::default();
+        let (_dir, guarded) = guarded_data(&transport).await;
+
+        let destination = Destination::Queue("target".to_string());
+        let error =
+            run(move |job| send_messages(guarded, vec![1], destination, true, 0, job)).await;
+
+        assert!(error.is_some());
+        assert!(transport.published.lock().unwrap().is_empty());
+    }
+
+    fn fake_queue(payloads: &[&str]) -> FakeTransport {
+        let transport = FakeTransport {
+            amqp: true,
+            ..Default::default()
+        };
+        transport
+            .queue
+            .lock()
+            .unwrap()
+            .extend(payloads.iter().map(|x| message(x)));
+        transport
+    }
+
+    #[tokio::test]
+    async fn failed_load_leaves_queue_unchanged() {
+        let transport = fake_queue(&["a", "b", "c"]);
+        let (dir, guarded) = guarded_data(&transport).await;
+        // saving fails as if the disk was full
+        rusqlite::Connection::open(dir.path().join("test.db"))
+            .unwrap()
+            .execute_batch(
+                "CREATE TRIGGER fail BEFORE INSERT ON messages
+                BEGIN SELECT RAISE(ABORT, 'database or disk is full'); END",
+            )
+            .unwrap();
+
+        let error = run(move |job| load_messages(guarded, "q".to_string(), None, None, job)).await;
+
+        assert!(error.unwrap().contains("disk is full"));
+        assert_eq!(transport.payloads(), ["a", "b", "c"]);
+    }
+
+    async fn stored_payloads(guarded: &Mutex<GuardedData>) -> Vec<String> {
+        let guarded = guarded.lock().await;
+        let queue_id = guarded.database.find_queue_by_name("q").unwrap().unwrap();
+        let messages = guarded
+            .database
+            .get_messages(&MessageSelector::AllInQueue(queue_id))
+            .unwrap();
+        messages.into_iter().map(|x| x.content.payload).collect()
+    }
+
+    fn payload_filter(substring: &str) -> Option<MessageFilter> {
+        MessageFilter::from_criteria(&FilterCriteria {
+            payload_contains: Some(substring.to_string()),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn load_moves_messages_to_database() {
+        let transport = fake_queue(&["a", "b", "c"]);
+        let (_dir, guarded) = guarded_data(&transport).await;
+
+        let g = Arc::clone(&guarded);
+        let error = run(move |job| load_messages(g, "q".to_string(), None, None, job)).await;
+
+        assert_eq!(error, None);
+        assert!(transport.payloads().is_empty());
+        assert_eq!(stored_payloads(&guarded).await, ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn load_leaves_unmatched_messages_in_place() {
+        let transport = fake_queue(&["a", "x1", "b", "x2"]);
+        let (_dir, guarded) = guarded_data(&transport).await;
+
+        let g = Arc::clone(&guarded);
+        let filter = payload_filter("x");
+        let error = run(move |job| load_messages(g, "q".to_string(), None, filter, job)).await;
+
+        assert_eq!(error, None);
+        assert_eq!(stored_payloads(&guarded).await, ["x1", "x2"]);
+        // nothing was published, the messages were returned unacknowledged
+        assert!(transport.published.lock().unwrap().is_empty());
+        assert_eq!(transport.payloads(), ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn load_stops_at_max_count_of_matches() {
+        let transport = fake_queue(&["x1", "a", "x2", "x3", "b"]);
+        let (_dir, guarded) = guarded_data(&transport).await;
+
+        let g = Arc::clone(&guarded);
+        let filter = payload_filter("x");
+        let error = run(move |job| load_messages(g, "q".to_string(), Some(2), filter, job)).await;
+
+        assert_eq!(error, None);
+        assert_eq!(stored_payloads(&guarded).await, ["x1", "x2"]);
+        assert_eq!(transport.payloads(), ["a", "x3", "b"]);
+    }
+
+    #[tokio::test]
+    async fn move_leaves_unmatched_messages_in_place() {
+        let transport = fake_queue(&["a", "x1", "b"]);
+        let endpoint = transport.serve_management_api().await;
+        let rabbitmq = Arc::new(Rabbitmq::with_transport(
+            endpoint,
+            Box::new(transport.clone()),
+        ));
+
+        let filter = payload_filter("x");
+        let error = run(move |job| {
+            move_messages(
+                rabbitmq,
+                "q".to_string(),
+                "target".to_string(),
+                None,
+                filter,
+                job,
+            )
+        })
+        .await;
+
+        assert_eq!(error, None);
+        let published = transport.published.lock().unwrap();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].1, "target");
+        assert_eq!(published[0].2.payload, "x1");
+        assert_eq!(transport.payloads(), ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn load_falls_back_to_management_api() {
+        let transport = FakeTransport {
+            amqp: false,
+            ..fake_queue(&["a", "x1", "b"])
+        };
+        let (_dir, guarded) = guarded_data(&transport).await;
+
+        let g = Arc::clone(&guarded);
+        let filter = payload_filter("x");
+        let error = run(move |job| load_messages(g, "q".to_string(), None, filter, job)).await;
+
+        assert_eq!(error, None);
+        assert_eq!(stored_payloads(&guarded).await, ["x1"]);
+        assert_eq!(transport.payloads(), ["a", "b"]);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::api::api_error::ApiError;
+use crate::api::{Connection, GuardedData};
+use crate::database::Database;
+use crate::rabbitmq::Rabbitmq;
+use crate::rmq_background::RmqBackground;
+use log::info;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Client, database and counter updates of one vhost
+#[derive(Clone)]
+pub struct VhostData {
+    pub guarded: Arc<Mutex<GuardedData>>,
+    pub rmq_background: RmqBackground,
+}
+
+/// Vhosts of one connection. The vhost of the connection is always open,
+/// other ones are opened when they are used for the first time
+#[derive(Clone)]
+pub struct Vhosts {
+    rabbitmq: Arc<Rabbitmq>,
+    /// Connection to the database file of the broker, other vhosts share it
+    database: Database,
+    default_vhost: String,
+    update_interval: Duration,
+    opened: Arc<Mutex<HashMap<String, VhostData>>>,
+}
+
+impl Vhosts {
+    pub fn new(connection: Connection) -> Self {
+        let default_vhost = connection.rabbitmq.get_connection_info().vhost;
+        let database = connection.database.clone();
+        let data = VhostData {
+            guarded: Arc::new(Mutex::new(GuardedData {
+                rabbitmq: Arc::clone(&connection.rabbitmq),
+                database: connection.database,
+            })),
+            rmq_background: connection.rmq_background,
+        };
+
+        Self {
+            rabbitmq: connection.rabbitmq,
+            database,
+            default_vhost: default_vhost.clone(),
+            update_interval: connection.update_interval,
+            opened: Arc::new(Mutex::new(HashMap::from([(default_vhost, data)]))),
+        }
+    }
+
+    pub fn rabbitmq(&self) -> &Rabbitmq {
+        &self.rabbitmq
+    }
+
+    pub async fn get(&self, vhost: Option<&str>) -> Result<VhostData, ApiError> {
+        let vhost = vhost.unwrap_or(&self.default_vhost);
+
+        // the lock is held while the vhost is opened so that it's opened only once
+        let mut opened = self.opened.lock().await;
+        if let Some(data) = opened.get(vhost) {
+            return Ok(data.clone());
+        }
+
+        let rabbitmq = Arc::new(
+            self.rabbitmq
+                .for_vhost(vhost)
+                .await?
+                .ok_or_else(|| ApiError::VhostNotFound(vhost.to_string()))?,
+        );
+        let database = self.database.for_vhost(vhost);
+        let data = VhostData {
+            rmq_background: RmqBackground::new(Arc::clone(&rabbitmq), self.update_interval),
+            guarded: Arc::new(Mutex::new(GuardedData { rabbitmq, database })),
+        };
+
+        info!("Opened vhost '{}'", vhost);
+        opened.insert(vhost.to_string(), data.clone());
+        Ok(data)
+    }
+}
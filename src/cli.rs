@@ -0,0 +1,129 @@
+use crate::api::operations;
+use crate::api::GuardedData;
+use crate::args::Command;
+use crate::database::Database;
+use crate::dtos::{JobKind, JobStatus};
+use crate::jobs::{JobHandle, Jobs};
+use crate::message_filter::MessageFilter;
+use crate::ndjson;
+use crate::rabbitmq::Rabbitmq;
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serde_json::Value;
+use std::fmt::Display;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Mutex;
+
+/// Runs a command without the web interface. An error makes the process exit with a failure code
+pub async fn run_command(command: Command, rabbitmq: Arc<Rabbitmq>) -> Result<()> {
+    match command {
+        Command::Dump { queue, out } => {
+            let messages = rabbitmq.peek_messages(&queue).await?;
+            std::fs::write(&out, ndjson::export_messages(&messages))?;
+            info!(
+                "Dumped {} messages of queue {} to {}",
+                messages.len(),
+                queue,
+                out.display()
+            );
+        }
+        Command::Restore { file, to } => {
+            let messages = ndjson::import_messages(&std::fs::read_to_string(&file)?)?;
+            let description = format!("Publish messages from {} to queue {}", file.display(), to);
+            run_job(JobKind::Publish, description, move |job| {
+                operations::publish_messages(rabbitmq, to, messages, job)
+            })
+            .await?;
+        }
+        Command::Move {
+            source,
+            destination,
+            selection,
+        } => {
+            let filter = MessageFilter::from_criteria(&selection.filter_criteria())?;
+            let description = format!("Move messages from queue {source} to queue {destination}");
+            run_job(JobKind::Move, description, move |job| {
+                operations::move_messages(
+                    rabbitmq,
+                    source,
+                    destination,
+                    selection.max_count,
+                    filter,
+                    job,
+                )
+            })
+            .await?;
+        }
+        Command::Load { queue, selection } => {
+            let filter = MessageFilter::from_criteria(&selection.filter_criteria())?;
+            let connection_info = rabbitmq.get_connection_info();
+            let database = Database::open(
+                &rabbitmq.database_name(),
+                &connection_info.domain,
+                &connection_info.vhost,
+            )?;
+            let guarded = Arc::new(Mutex::new(GuardedData { rabbitmq, database }));
+            let description = format!("Load messages from queue {queue}");
+            run_job(JobKind::Load, description, move |job| {
+                operations::load_messages(guarded, queue, selection.max_count, filter, job)
+            })
+            .await?;
+        }
+        Command::Purge { queue } => {
+            let purged = rabbitmq.purge_queue(&queue).await?;
+            info!("Purged {} messages of queue {}", purged, queue);
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the operation as a job to report its progress. Ctrl+C cancels the job
+/// at the next safe point instead of killing the process in the middle of a batch
+async fn run_job<F, Fut, E>(kind: JobKind, description: String, f: F) -> Result<Value>
+where
+    F: FnOnce(JobHandle) -> Fut,
+    Fut: Future<Output = Result<Value, E>> + Send + 'static,
+    E: Display,
+{
+    let jobs = Jobs::new();
+    let mut updates = jobs.subscribe();
+    let id = jobs.spawn(kind, description, f).id;
+
+    loop {
+        let info = tokio::select! {
+            update = updates.recv() => match update {
+                Ok(info) => info,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return Err(anyhow!("Job {id} stopped reporting")),
+            },
+            _ = tokio::signal::ctrl_c() => {
+                warn!("Cancelling, waiting for the current batch to finish");
+                jobs.cancel(id)?;
+                continue;
+            }
+        };
+
+        match info.status {
+            JobStatus::Running | JobStatus::Paused => {
+                if let Some(total) = info.total {
+                    info!("[progress] {}/{}", info.processed, total);
+                }
+            }
+            JobStatus::Completed => {
+                let result = info.result.unwrap_or_default();
+                info!("Completed: {}", result);
+                return Ok(result);
+            }
+            JobStatus::Cancelled => return Err(anyhow!("{} was cancelled", info.description)),
+            JobStatus::Failed => {
+                return Err(anyhow!(
+                    "{} failed: {}",
+                    info.description,
+                    info.error.unwrap_or_default()
+                ));
+            }
+        }
+    }
+}
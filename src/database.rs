@@ -1,15 +1,31 @@
-use crate::dtos::Message;
+use crate::dtos::{
+    Change, HistoryEntry, Message, MessageContent, MessageProperties, MessagesPage,
+    MessagesPageQuery, PayloadEncoding, SearchHit, SortOrder,
+};
 use crate::types::db_types::LocalQueue;
-use anyhow::Result;
-use rusqlite::{Connection, OptionalExtension, Row, ToSql};
-use serde_json::Map;
+use log::info;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{Connection, OptionalExtension, Params, Row, ToSql};
+use serde_json::{Map, Value};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
+mod migrations;
+
 pub type QueueId = u32;
 pub type MessageId = u32;
+pub type HistoryId = u32;
+
+/// History entries are kept for this long, older ones are removed when the database is opened
+const HISTORY_RETENTION_DAYS: u64 = 30;
+const HISTORY_RETENTION: Duration = Duration::from_secs(HISTORY_RETENTION_DAYS * 24 * 60 * 60);
 
+/// One database file can be shared by several vhosts of a broker, see `Database::for_vhost`
+#[derive(Clone)]
 pub struct Database {
-    connection: Connection,
+    connection: Arc<Mutex<Connection>>,
     vhost: String,
 }
 
@@ -19,52 +35,59 @@ pub enum DatabaseError {
     Database(#[from] rusqlite::Error),
     #[error("{:?}", .0)]
     Serialization(#[from] serde_json::Error),
+    #[error("History entry {} not found", .0)]
+    HistoryNotFound(HistoryId),
+    #[error("Change {} can't be undone: {}", .0, .1)]
+    CannotUndo(HistoryId, &'static str),
+    #[error("{:?}", .0)]
+    Io(#[from] std::io::Error),
+    #[error("Database has schema version {}, but this version of rmq_tools supports up to {}", .0, .1)]
+    UnsupportedSchemaVersion(u32, u32),
 }
 
 impl Database {
-    pub fn new(filename: &str, vhost: &str) -> Result<Database> {
-        let connection = Connection::open(format!("{}.db", filename))?;
-
-        connection.execute(
-            "CREATE TABLE IF NOT EXISTS queues (
-            id    INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
-            name  TEXT NOT NULL,
-            vhost TEXT NOT NULL
-        )",
-            (),
-        )?;
+    pub fn new(filename: &str, vhost: &str) -> Result<Database, DatabaseError> {
+        let mut connection = Connection::open(format!("{}.db", filename))?;
+        migrations::migrate(&mut connection)?;
 
-        connection.execute(
-            "CREATE UNIQUE INDEX IF NOT EXISTS idx_queue_name_vhost
-            ON queues(name, vhost)",
-            (),
-        )?;
-
-        connection.execute(
-            "CREATE TABLE IF NOT EXISTS messages (
-            id        INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
-            queue_id  TEXT NOT NULL,
-            headers   TEXT NOT NULL,
-            payload   TEXT NOT NULL,
-            FOREIGN KEY(queue_id) REFERENCES queues(id)
-        )",
-            (),
-        )?;
-
-        connection.execute(
-            "CREATE INDEX IF NOT EXISTS idx_queue_id
-            ON messages(queue_id)",
-            (),
-        )?;
+        let database = Self {
+            connection: Arc::new(Mutex::new(connection)),
+            vhost: vhost.to_string(),
+        };
+        let pruned = database.prune_history(HISTORY_RETENTION)?;
+        if pruned > 0 {
+            info!("Removed {pruned} history entries older than {HISTORY_RETENTION_DAYS} days");
+        }
+        Ok(database)
+    }
 
-        Ok(Self {
-            connection,
+    /// Returns a handle for another vhost that uses the same connection
+    pub fn for_vhost(&self, vhost: &str) -> Database {
+        Self {
+            connection: Arc::clone(&self.connection),
             vhost: vhost.to_string(),
-        })
+        }
+    }
+
+    /// Opens the database, taking over the file of an earlier version if there is no database yet.
+    /// That file was shared by all brokers on the host, so it's renamed for the first one opened
+    pub fn open(
+        filename: &str,
+        legacy_filename: &str,
+        vhost: &str,
+    ) -> Result<Database, DatabaseError> {
+        let path = format!("{}.db", filename);
+        let legacy_path = format!("{}.db", legacy_filename);
+        if !Path::new(&path).exists() && Path::new(&legacy_path).exists() {
+            std::fs::rename(&legacy_path, &path)?;
+            info!("Renamed database {legacy_path} to {path}");
+        }
+        Self::new(filename, vhost)
     }
 
     pub fn get_queues(&self) -> Result<Vec<LocalQueue>, DatabaseError> {
-        let mut stmt = self.connection.prepare(
+        let connection = self.connection.lock().unwrap();
+        let mut stmt = connection.prepare(
             r#"
             SELECT q.id, q.name, coalesce(m.count, 0) FROM queues q
             LEFT JOIN (
@@ -85,27 +108,35 @@ impl Database {
     }
 
     pub fn find_queue_by_name(&self, name: &str) -> Result<Option<QueueId>, DatabaseError> {
-        let mut stmt = self
-            .connection
-            .prepare("SELECT id FROM queues WHERE name=? AND vhost=?")?;
+        let connection = self.connection.lock().unwrap();
+        let mut stmt = connection.prepare("SELECT id FROM queues WHERE name=? AND vhost=?")?;
         let result = stmt
             .query_one([name, &self.vhost], |row| row.get(0))
             .optional()?;
         Ok(result)
     }
 
+    pub fn find_queue_name(&self, queue_id: QueueId) -> Result<Option<String>, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
+        let mut stmt = connection.prepare("SELECT name FROM queues WHERE id=?")?;
+        let result = stmt.query_one([queue_id], |row| row.get(0)).optional()?;
+        Ok(result)
+    }
+
     fn get_messages_in_queue(&self, queue_id: QueueId) -> Result<Vec<Message>, DatabaseError> {
-        let mut stmt = self
-            .connection
-            .prepare("SELECT id, payload, headers FROM messages WHERE queue_id = ? ORDER BY id")?;
+        let connection = self.connection.lock().unwrap();
+        let mut stmt = connection.prepare(&format!(
+            "SELECT id, {CONTENT_COLUMNS} FROM messages WHERE queue_id = ? ORDER BY id"
+        ))?;
         let vec = stmt.query_map([queue_id], message_from_row)?;
         Ok(vec.collect::<Result<Vec<_>, _>>()?)
     }
 
     fn get_messages_by_ids(&self, ids: &[MessageId]) -> Result<Vec<Message>, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
         let vars = repeat_vars(ids.len());
-        let mut stmt = self.connection.prepare(&format!(
-            "SELECT id, payload, headers FROM messages WHERE id IN ({vars}) ORDER BY id"
+        let mut stmt = connection.prepare(&format!(
+            "SELECT id, {CONTENT_COLUMNS} FROM messages WHERE id IN ({vars}) ORDER BY id"
         ))?;
         let vec = stmt.query_map(rusqlite::params_from_iter(ids), message_from_row)?;
         Ok(vec.collect::<Result<Vec<_>, _>>()?)
@@ -118,8 +149,108 @@ impl Database {
         }
     }
 
+    /// Selected messages that belong to the queue. Ids of messages in other queues are skipped
+    pub fn get_queue_messages(
+        &self,
+        queue_id: QueueId,
+        selector: &MessageSelector,
+    ) -> Result<Vec<Message>, DatabaseError> {
+        match selector {
+            MessageSelector::AllInQueue(_) => self.get_messages_in_queue(queue_id),
+            MessageSelector::WithIds(ids) => {
+                let connection = self.connection.lock().unwrap();
+                let vars = repeat_vars(ids.len());
+                let mut stmt = connection.prepare(&format!(
+                    "SELECT id, {CONTENT_COLUMNS} FROM messages WHERE queue_id = ? AND id IN ({vars}) ORDER BY id"
+                ))?;
+                let params = std::iter::once(&queue_id).chain(ids.iter());
+                let vec = stmt.query_map(rusqlite::params_from_iter(params), message_from_row)?;
+                Ok(vec.collect::<Result<Vec<_>, _>>()?)
+            }
+        }
+    }
+
+    pub fn get_messages_page(
+        &self,
+        queue_id: QueueId,
+        query: &MessagesPageQuery,
+    ) -> Result<MessagesPage, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
+        let search = query.search.as_deref().filter(|s| !s.is_empty());
+        let search_condition = match search {
+            None => "",
+            Some(_) => {
+                r"AND (payload LIKE :pattern ESCAPE '\' OR headers LIKE :pattern ESCAPE '\')"
+            }
+        };
+        let pattern = search.map(|s| format!("%{}%", escape_like(s)));
+        let mut params: Vec<(&str, &dyn ToSql)> = vec![(":queue_id", &queue_id)];
+        if let Some(pattern) = &pattern {
+            params.push((":pattern", pattern));
+        }
+
+        let total = connection.query_one(
+            &format!("SELECT count(*) FROM messages WHERE queue_id = :queue_id {search_condition}"),
+            &params[..],
+            |row| row.get(0),
+        )?;
+
+        let order = match query.sort {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        };
+        // a negative limit means no limit in SQLite
+        let limit = query.limit.map_or(-1, |x| x.min(i64::MAX as u64) as i64);
+        let offset = query.offset.min(i64::MAX as u64) as i64;
+        params.push((":limit", &limit));
+        params.push((":offset", &offset));
+
+        let mut stmt = connection.prepare(&format!(
+            "SELECT id, {CONTENT_COLUMNS} FROM messages WHERE queue_id = :queue_id {search_condition}
+            ORDER BY id {order} LIMIT :limit OFFSET :offset"
+        ))?;
+        let messages = stmt
+            .query_map(&params[..], message_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(MessagesPage { messages, total })
+    }
+
+    /// Finds messages in all queues of the vhost that contain every word of the text
+    pub fn search_messages(&self, text: &str, limit: u32) -> Result<Vec<SearchHit>, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
+        let Some(match_expression) = fts_match_expression(text) else {
+            return Ok(vec![]);
+        };
+
+        // the markers are replaced with tags only after the message text is escaped
+        let mut stmt = connection.prepare(&format!(
+            r#"
+            SELECT m.id, q.id, q.name,
+                snippet(messages_fts, 0, '{MATCH_START}', '{MATCH_END}', '...', 16),
+                snippet(messages_fts, 1, '{MATCH_START}', '{MATCH_END}', '...', 16)
+            FROM messages_fts f
+            JOIN messages m ON m.id = f.rowid
+            JOIN queues q ON q.id = m.queue_id
+            WHERE messages_fts MATCH ? AND q.vhost = ?
+            ORDER BY f.rank
+            LIMIT ?
+        "#
+        ))?;
+        let vec = stmt.query_map((match_expression, &self.vhost, limit), |row| {
+            Ok(SearchHit {
+                message_id: row.get(0)?,
+                queue_id: row.get(1)?,
+                queue_name: row.get(2)?,
+                payload_snippet: highlight_snippet(&row.get::<_, String>(3)?),
+                headers_snippet: highlight_snippet(&row.get::<_, String>(4)?),
+            })
+        })?;
+        Ok(vec.collect::<Result<Vec<_>, _>>()?)
+    }
+
     pub fn create_queue(&self, name: &str) -> Result<QueueId, DatabaseError> {
-        self.connection.execute(
+        self.connection.lock().unwrap().execute(
             "INSERT INTO queues (name, vhost) VALUES (?, ?)",
             [name, &self.vhost],
         )?;
@@ -135,65 +266,381 @@ impl Database {
         message_id: MessageId,
         payload: &str,
     ) -> Result<bool, DatabaseError> {
-        let num_changed = self.connection.execute(
+        let connection = self.connection.lock().unwrap();
+        let transaction = connection.unchecked_transaction()?;
+        record_history(
+            &transaction,
+            Change::Edit,
+            "id = ? AND queue_id = ?",
+            (message_id, queue_id),
+        )?;
+        let num_changed = transaction.execute(
             "UPDATE messages SET payload = ? WHERE id = ? AND queue_id = ?",
             (payload, message_id, queue_id),
         )?;
+        transaction.commit()?;
+        Ok(num_changed == 1)
+    }
+
+    /// Replaces the payloads of many messages of the queue in one transaction
+    pub fn set_message_payloads(
+        &self,
+        queue_id: QueueId,
+        payloads: &[(MessageId, String)],
+    ) -> Result<usize, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
+        let transaction = connection.unchecked_transaction()?;
+        let mut updated = 0;
+        for (message_id, payload) in payloads {
+            record_history(
+                &transaction,
+                Change::Edit,
+                "id = ? AND queue_id = ?",
+                (message_id, queue_id),
+            )?;
+            updated += transaction.execute(
+                "UPDATE messages SET payload = ? WHERE id = ? AND queue_id = ?",
+                (payload, message_id, queue_id),
+            )?;
+        }
+        transaction.commit()?;
+        Ok(updated)
+    }
+
+    pub fn set_message_metadata(
+        &self,
+        queue_id: QueueId,
+        message_id: MessageId,
+        properties: &MessageProperties,
+        headers: &Map<String, Value>,
+    ) -> Result<bool, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
+        let assignments = PROPERTY_COLUMNS
+            .map(|(column, _)| format!("{column} = ?"))
+            .join(", ");
+        let headers_json = serde_json::to_string(headers)?;
+        let mut values: Vec<&dyn ToSql> = vec![&headers_json];
+        values.extend(property_values(properties));
+        values.push(&message_id);
+        values.push(&queue_id);
+
+        let transaction = connection.unchecked_transaction()?;
+        record_history(
+            &transaction,
+            Change::Edit,
+            "id = ? AND queue_id = ?",
+            (message_id, queue_id),
+        )?;
+        let num_changed = transaction.execute(
+            &format!(
+                "UPDATE messages SET headers = ?, {assignments} WHERE id = ? AND queue_id = ?"
+            ),
+            &values[..],
+        )?;
+        transaction.commit()?;
         Ok(num_changed == 1)
     }
 
+    /// Sets the header on the messages, or removes it when the value is `None`.
+    /// Returns the number of messages that were changed
+    pub fn set_header(
+        &self,
+        queue_id: QueueId,
+        selector: &MessageSelector,
+        name: &str,
+        value: Option<&Value>,
+    ) -> Result<usize, DatabaseError> {
+        let messages = self.get_queue_messages(queue_id, selector)?;
+        let connection = self.connection.lock().unwrap();
+        let transaction = connection.unchecked_transaction()?;
+        let mut updated = 0;
+        for mut message in messages {
+            let headers = &mut message.content.headers;
+            let changed = match value {
+                Some(value) => {
+                    headers.insert(name.to_string(), value.clone()).as_ref() != Some(value)
+                }
+                None => headers.remove(name).is_some(),
+            };
+            if !changed {
+                continue;
+            }
+
+            record_history(
+                &transaction,
+                Change::Edit,
+                "id = ? AND queue_id = ?",
+                (message.id, queue_id),
+            )?;
+            updated += transaction.execute(
+                "UPDATE messages SET headers = ? WHERE id = ? AND queue_id = ?",
+                (serde_json::to_string(headers)?, message.id, queue_id),
+            )?;
+        }
+        transaction.commit()?;
+        Ok(updated)
+    }
+
     pub fn save_messages(
         &self,
         queue_id: QueueId,
-        messages: &[(String, Map<String, serde_json::Value>)],
+        messages: &[MessageContent],
     ) -> Result<(), DatabaseError> {
-        let vars = {
-            let mut s = "(?,?,?),".repeat(messages.len());
-            s.pop(); // Remove trailing comma
-            s
-        };
+        let connection = self.connection.lock().unwrap();
+        let columns = property_column_names();
+        let vars = repeat_vars(7 + PROPERTY_COLUMNS.len());
 
-        let headers_json = messages
-            .iter()
-            .map(|(_, headers)| serde_json::to_string(headers))
-            .collect::<Result<Vec<_>, _>>()?;
+        let transaction = connection.unchecked_transaction()?;
+        {
+            let mut stmt = transaction.prepare(&format!(
+                "INSERT INTO messages (queue_id, payload, payload_encoding, headers, exchange, routing_key, redelivered, {columns}) VALUES ({vars})"
+            ))?;
 
-        let mut values: Vec<&dyn ToSql> = Vec::with_capacity(3 * messages.len());
-        for i in 0..messages.len() {
-            values.push(&queue_id);
-            values.push(&messages[i].0);
-            values.push(&headers_json[i]);
+            for message in messages {
+                let headers_json = serde_json::to_string(&message.headers)?;
+                let mut values: Vec<&dyn ToSql> = vec![
+                    &queue_id,
+                    &message.payload,
+                    &message.payload_encoding,
+                    &headers_json,
+                    &message.exchange,
+                    &message.routing_key,
+                    &message.redelivered,
+                ];
+                values.extend(property_values(&message.properties));
+                stmt.execute(&values[..])?;
+            }
         }
+        transaction.commit()?;
 
-        self.connection.execute(
-            &format!("INSERT INTO messages (queue_id, payload, headers) VALUES {vars}"),
-            &values[..],
-        )?;
         Ok(())
     }
 
-    fn delete_messages_by_ids(&self, ids: &[MessageId]) -> Result<usize, DatabaseError> {
-        let vars = repeat_vars(ids.len());
-        let deleted = self.connection.execute(
-            &format!("DELETE FROM messages WHERE id IN ({vars})"),
-            rusqlite::params_from_iter(ids),
-        )?;
+    /// Deletes the messages and keeps them in the history so that they can be restored
+    pub fn delete_messages(&self, selector: &MessageSelector) -> Result<usize, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
+        let transaction = connection.unchecked_transaction()?;
+        let deleted = match selector {
+            MessageSelector::AllInQueue(queue_id) => {
+                record_history(&transaction, Change::Delete, "queue_id = ?", [queue_id])?;
+                transaction.execute("DELETE FROM messages WHERE queue_id=?", [queue_id])?
+            }
+            MessageSelector::WithIds(ids) => {
+                let condition = format!("id IN ({})", repeat_vars(ids.len()));
+                let params = rusqlite::params_from_iter(*ids);
+                record_history(&transaction, Change::Delete, &condition, params.clone())?;
+                transaction.execute(&format!("DELETE FROM messages WHERE {condition}"), params)?
+            }
+        };
+        transaction.commit()?;
         Ok(deleted)
     }
 
-    fn delete_all_messages(&self, queue_id: QueueId) -> Result<usize, DatabaseError> {
-        let deleted = self
-            .connection
-            .execute("DELETE FROM messages WHERE queue_id=?", [queue_id])?;
-        Ok(deleted)
+    /// Deletes messages that were published to RabbitMQ. They aren't kept in the history,
+    /// because restoring them would duplicate the published messages
+    pub fn delete_sent_message(&self, message_id: MessageId) -> Result<bool, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
+        let deleted = connection.execute("DELETE FROM messages WHERE id=?", [message_id])?;
+        Ok(deleted == 1)
     }
 
-    pub fn delete_messages(&self, selector: &MessageSelector) -> Result<usize, DatabaseError> {
-        match selector {
-            MessageSelector::AllInQueue(queue_id) => self.delete_all_messages(*queue_id),
-            MessageSelector::WithIds(ids) => self.delete_messages_by_ids(ids),
+    /// Returns the previous versions of the message, the latest first
+    pub fn get_message_history(
+        &self,
+        queue_id: QueueId,
+        message_id: MessageId,
+    ) -> Result<Vec<HistoryEntry>, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
+        let mut stmt = connection.prepare(&format!(
+            "SELECT {HISTORY_COLUMNS}, id, {CONTENT_COLUMNS} FROM message_history
+            WHERE id = ? AND queue_id = ? ORDER BY history_id DESC"
+        ))?;
+        let vec = stmt.query_map((message_id, queue_id), history_entry_from_row)?;
+        Ok(vec.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Returns deleted messages of the queue that weren't restored, the latest deleted first
+    pub fn get_deleted_messages(
+        &self,
+        queue_id: QueueId,
+    ) -> Result<Vec<HistoryEntry>, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
+        let mut stmt = connection.prepare(&format!(
+            "SELECT {HISTORY_COLUMNS}, id, {CONTENT_COLUMNS} FROM message_history
+            WHERE queue_id = ? AND change = ? AND undone = 0 ORDER BY history_id DESC"
+        ))?;
+        let vec = stmt.query_map((queue_id, Change::Delete), history_entry_from_row)?;
+        Ok(vec.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Restores the version of the message that was replaced by an edit, or a deleted message.
+    /// All changes are undone in one transaction, the latest first. Changes of other queues
+    /// aren't found
+    pub fn undo_changes(
+        &self,
+        queue_id: QueueId,
+        history_ids: &[HistoryId],
+    ) -> Result<usize, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
+        let mut history_ids = history_ids.to_vec();
+        history_ids.sort_unstable_by(|a, b| b.cmp(a));
+        history_ids.dedup();
+
+        let transaction = connection.unchecked_transaction()?;
+        for &history_id in &history_ids {
+            let (message_id, change, undone): (MessageId, Change, bool) = transaction
+                .query_one(
+                    "SELECT id, change, undone FROM message_history
+                    WHERE history_id = ? AND queue_id = ?",
+                    (history_id, queue_id),
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .optional()?
+                .ok_or(DatabaseError::HistoryNotFound(history_id))?;
+            if undone {
+                return Err(DatabaseError::CannotUndo(history_id, "already undone"));
+            }
+
+            let message_exists: bool = transaction.query_one(
+                "SELECT count(*) > 0 FROM messages WHERE id = ?",
+                [message_id],
+                |row| row.get(0),
+            )?;
+
+            match change {
+                Change::Edit => {
+                    if !message_exists {
+                        return Err(DatabaseError::CannotUndo(history_id, "message was deleted"));
+                    }
+                    // the undo is an edit too, so it can be undone as well
+                    record_history(&transaction, Change::Edit, "id = ?", [message_id])?;
+                    transaction.execute(
+                        &format!(
+                            "UPDATE messages SET ({CONTENT_COLUMNS}) = (
+                                SELECT {CONTENT_COLUMNS} FROM message_history WHERE history_id = ?
+                            ) WHERE id = ?"
+                        ),
+                        (history_id, message_id),
+                    )?;
+                }
+                Change::Delete => {
+                    if message_exists {
+                        return Err(DatabaseError::CannotUndo(history_id, "message exists"));
+                    }
+                    transaction.execute(
+                        &format!(
+                            "INSERT INTO messages (id, queue_id, {CONTENT_COLUMNS})
+                            SELECT id, queue_id, {CONTENT_COLUMNS} FROM message_history
+                            WHERE history_id = ?"
+                        ),
+                        [history_id],
+                    )?;
+                }
+            }
+
+            transaction.execute(
+                "UPDATE message_history SET undone = 1 WHERE history_id = ?",
+                [history_id],
+            )?;
         }
+        transaction.commit()?;
+
+        Ok(history_ids.len())
     }
+
+    /// Removes the whole history of the queue, its changes can't be undone anymore
+    pub fn purge_history(&self, queue_id: QueueId) -> Result<usize, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
+        let purged =
+            connection.execute("DELETE FROM message_history WHERE queue_id = ?", [queue_id])?;
+        Ok(purged)
+    }
+
+    /// Removes history entries of all queues that are older than `retention`
+    pub fn prune_history(&self, retention: Duration) -> Result<usize, DatabaseError> {
+        let connection = self.connection.lock().unwrap();
+        let oldest = unix_time_millis() - retention.as_millis() as i64;
+        let pruned =
+            connection.execute("DELETE FROM message_history WHERE changed_at < ?", [oldest])?;
+        Ok(pruned)
+    }
+}
+
+/// Columns of `messages` table except `id` and `queue_id`
+const CONTENT_COLUMNS: &str = "payload, headers, payload_encoding, content_type, \
+    content_encoding, delivery_mode, priority, correlation_id, reply_to, expiration, message_id, \
+    timestamp, type, user_id, app_id, cluster_id, exchange, routing_key, redelivered";
+
+/// Columns of `message_history` table that describe the change
+const HISTORY_COLUMNS: &str = "history_id, change, changed_at, undone";
+/// Characters from the private use area that mark found words in search snippets
+const MATCH_START: char = '\u{E000}';
+const MATCH_END: char = '\u{E001}';
+
+const PROPERTY_COLUMNS: [(&str, &str); 13] = [
+    ("content_type", "TEXT"),
+    ("content_encoding", "TEXT"),
+    ("delivery_mode", "INTEGER"),
+    ("priority", "INTEGER"),
+    ("correlation_id", "TEXT"),
+    ("reply_to", "TEXT"),
+    ("expiration", "TEXT"),
+    ("message_id", "TEXT"),
+    ("timestamp", "INTEGER"),
+    ("type", "TEXT"),
+    ("user_id", "TEXT"),
+    ("app_id", "TEXT"),
+    ("cluster_id", "TEXT"),
+];
+
+fn property_column_names() -> String {
+    PROPERTY_COLUMNS.map(|(column, _)| column).join(", ")
+}
+
+/// Values of the properties in the order of `PROPERTY_COLUMNS`
+fn property_values(properties: &MessageProperties) -> [&dyn ToSql; 13] {
+    [
+        &properties.content_type,
+        &properties.content_encoding,
+        &properties.delivery_mode,
+        &properties.priority,
+        &properties.correlation_id,
+        &properties.reply_to,
+        &properties.expiration,
+        &properties.message_id,
+        &properties.timestamp,
+        &properties.message_type,
+        &properties.user_id,
+        &properties.app_id,
+        &properties.cluster_id,
+    ]
+}
+
+/// Copies the current version of the messages matching the condition to the history
+fn record_history(
+    connection: &Connection,
+    change: Change,
+    condition: &str,
+    params: impl Params,
+) -> Result<(), DatabaseError> {
+    let changed_at = unix_time_millis();
+    let change = change.as_str();
+    connection.execute(
+        &format!(
+            "INSERT INTO message_history (change, changed_at, id, queue_id, {CONTENT_COLUMNS})
+            SELECT '{change}', {changed_at}, id, queue_id, {CONTENT_COLUMNS} FROM messages
+            WHERE {condition}"
+        ),
+        params,
+    )?;
+    Ok(())
+}
+
+fn unix_time_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is after the Unix epoch")
+        .as_millis() as i64
 }
 
 fn repeat_vars(count: usize) -> String {
@@ -204,16 +651,377 @@ fn repeat_vars(count: usize) -> String {
     s
 }
 
+/// Quotes every word so that user input is never interpreted as FTS5 query syntax
+fn fts_match_expression(text: &str) -> Option<String> {
+    let words = text
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>();
+    (!words.is_empty()).then(|| words.join(" "))
+}
+
+/// Escapes HTML in a snippet and turns the match markers into `<mark>` tags
+fn highlight_snippet(snippet: &str) -> String {
+    let mut result = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            MATCH_START => result.push_str("<mark>"),
+            MATCH_END => result.push_str("</mark>"),
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Escapes `%`, `_` and the escape character itself for a `LIKE ... ESCAPE '\'` pattern
+fn escape_like(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
 fn message_from_row(row: &Row) -> Result<Message, rusqlite::Error> {
-    let headers: String = row.get(2)?;
+    let headers: String = row.get("headers")?;
     Ok(Message {
-        id: row.get(0)?,
-        payload: row.get(1)?,
-        headers: serde_json::from_str(&headers).unwrap(),
+        id: row.get("id")?,
+        content: MessageContent {
+            payload: row.get("payload")?,
+            payload_encoding: row.get("payload_encoding")?,
+            properties: MessageProperties {
+                content_type: row.get("content_type")?,
+                content_encoding: row.get("content_encoding")?,
+                delivery_mode: row.get("delivery_mode")?,
+                priority: row.get("priority")?,
+                correlation_id: row.get("correlation_id")?,
+                reply_to: row.get("reply_to")?,
+                expiration: row.get("expiration")?,
+                message_id: row.get("message_id")?,
+                timestamp: row.get("timestamp")?,
+                message_type: row.get("type")?,
+                user_id: row.get("user_id")?,
+                app_id: row.get("app_id")?,
+                cluster_id: row.get("cluster_id")?,
+            },
+            headers: serde_json::from_str(&headers).unwrap(),
+            exchange: row.get("exchange")?,
+            routing_key: row.get("routing_key")?,
+            redelivered: row.get("redelivered")?,
+        },
+    })
+}
+
+fn history_entry_from_row(row: &Row) -> Result<HistoryEntry, rusqlite::Error> {
+    Ok(HistoryEntry {
+        history_id: row.get("history_id")?,
+        change: row.get("change")?,
+        changed_at: row.get("changed_at")?,
+        undone: row.get("undone")?,
+        message: message_from_row(row)?,
     })
 }
 
+impl ToSql for PayloadEncoding {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for PayloadEncoding {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let s = value.as_str()?;
+        PayloadEncoding::parse(s).ok_or_else(|| FromSqlError::Other(s.into()))
+    }
+}
+
+impl ToSql for Change {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for Change {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let s = value.as_str()?;
+        Change::parse(s).ok_or_else(|| FromSqlError::Other(s.into()))
+    }
+}
+
 pub enum MessageSelector<'a> {
     AllInQueue(QueueId),
     WithIds(&'a [QueueId]),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn open_database() -> (TempDir, Database) {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test");
+        let database = Database::new(path.to_str().unwrap(), "/").unwrap();
+        (dir, database)
+    }
+
+    fn message(payload: &str) -> MessageContent {
+        serde_json::from_value(json!({ "payload": payload })).unwrap()
+    }
+
+    #[test]
+    fn open_takes_over_legacy_database() {
+        let dir = TempDir::new().unwrap();
+        let legacy = dir.path().join("localhost");
+        let legacy = legacy.to_str().unwrap();
+        let name = dir.path().join("localhost_15672");
+        let name = name.to_str().unwrap();
+        Database::new(legacy, "/")
+            .unwrap()
+            .create_queue("q")
+            .unwrap();
+
+        let database = Database::open(name, legacy, "/").unwrap();
+
+        assert!(database.find_queue_by_name("q").unwrap().is_some());
+        assert!(!Path::new(&format!("{legacy}.db")).exists());
+    }
+
+    #[test]
+    fn search_escapes_html_in_snippets() {
+        let (_dir, database) = open_database();
+        let queue_id = database.create_queue("q").unwrap();
+        database
+            .save_messages(queue_id, &[message("<img src=x onerror=alert(1)> found")])
+            .unwrap();
+
+        let hits = database.search_messages("found", 10).unwrap();
+
+        assert_eq!(
+            hits[0].payload_snippet,
+            "&lt;img src=x onerror=alert(1)&gt; <mark>found</mark>"
+        );
+    }
+
+    #[test]
+    fn undo_restores_edited_payload() {
+        let (_dir, database) = open_database();
+        let queue_id = database.create_queue("q").unwrap();
+        database
+            .save_messages(queue_id, &[message("before")])
+            .unwrap();
+        let message_id = database
+            .get_messages(&MessageSelector::AllInQueue(queue_id))
+            .unwrap()[0]
+            .id;
+        database
+            .set_message_payload(queue_id, message_id, "after")
+            .unwrap();
+
+        let history = database.get_message_history(queue_id, message_id).unwrap();
+        database
+            .undo_changes(queue_id, &[history[0].history_id])
+            .unwrap();
+
+        let messages = database
+            .get_messages(&MessageSelector::AllInQueue(queue_id))
+            .unwrap();
+        assert_eq!(messages[0].content.payload, "before");
+    }
+
+    #[test]
+    fn set_message_payloads_ignores_messages_of_other_queues() {
+        let (_dir, database) = open_database();
+        let queue_id = database.create_queue("q").unwrap();
+        let other_queue_id = database.create_queue("other").unwrap();
+        database
+            .save_messages(other_queue_id, &[message("other")])
+            .unwrap();
+        let other = &database
+            .get_messages(&MessageSelector::AllInQueue(other_queue_id))
+            .unwrap()[0];
+
+        let updated = database
+            .set_message_payloads(queue_id, &[(other.id, "changed".to_string())])
+            .unwrap();
+
+        assert_eq!(updated, 0);
+        let messages = database
+            .get_messages(&MessageSelector::AllInQueue(other_queue_id))
+            .unwrap();
+        assert_eq!(messages[0].content.payload, "other");
+        assert!(database
+            .get_message_history(other_queue_id, other.id)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn get_queue_messages_skips_ids_of_other_queues() {
+        let (_dir, database) = open_database();
+        let queue_id = database.create_queue("q").unwrap();
+        let other_queue_id = database.create_queue("other").unwrap();
+        database.save_messages(queue_id, &[message("own")]).unwrap();
+        database
+            .save_messages(other_queue_id, &[message("other")])
+            .unwrap();
+        let ids: Vec<_> = database
+            .get_messages(&MessageSelector::AllInQueue(queue_id))
+            .unwrap()
+            .into_iter()
+            .chain(
+                database
+                    .get_messages(&MessageSelector::AllInQueue(other_queue_id))
+                    .unwrap(),
+            )
+            .map(|x| x.id)
+            .collect();
+
+        let messages = database
+            .get_queue_messages(queue_id, &MessageSelector::WithIds(&ids))
+            .unwrap();
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content.payload, "own");
+    }
+
+    #[test]
+    fn set_header_ignores_messages_of_other_queues() {
+        let (_dir, database) = open_database();
+        let queue_id = database.create_queue("q").unwrap();
+        let other_queue_id = database.create_queue("other").unwrap();
+        database
+            .save_messages(other_queue_id, &[message("other")])
+            .unwrap();
+        let other = &database
+            .get_messages(&MessageSelector::AllInQueue(other_queue_id))
+            .unwrap()[0];
+
+        let updated = database
+            .set_header(
+                queue_id,
+                &MessageSelector::WithIds(&[other.id]),
+                "x-test",
+                Some(&json!("changed")),
+            )
+            .unwrap();
+
+        assert_eq!(updated, 0);
+        let messages = database
+            .get_messages(&MessageSelector::AllInQueue(other_queue_id))
+            .unwrap();
+        assert!(messages[0].content.headers.is_empty());
+        assert!(database
+            .get_message_history(other_queue_id, other.id)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn undo_restores_deleted_message() {
+        let (_dir, database) = open_database();
+        let queue_id = database.create_queue("q").unwrap();
+        database
+            .save_messages(queue_id, &[message("deleted")])
+            .unwrap();
+        database
+            .delete_messages(&MessageSelector::AllInQueue(queue_id))
+            .unwrap();
+
+        let deleted = database.get_deleted_messages(queue_id).unwrap();
+        database
+            .undo_changes(queue_id, &[deleted[0].history_id])
+            .unwrap();
+
+        let messages = database
+            .get_messages(&MessageSelector::AllInQueue(queue_id))
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].id, deleted[0].message.id);
+        assert_eq!(messages[0].content.payload, "deleted");
+        assert!(database.get_deleted_messages(queue_id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn undo_ignores_changes_of_other_queues() {
+        let (_dir, database) = open_database();
+        let queue_id = database.create_queue("q").unwrap();
+        let other_queue_id = database.create_queue("other").unwrap();
+        database
+            .save_messages(queue_id, &[message("deleted")])
+            .unwrap();
+        database
+            .delete_messages(&MessageSelector::AllInQueue(queue_id))
+            .unwrap();
+        let history_id = database.get_deleted_messages(queue_id).unwrap()[0].history_id;
+
+        let result = database.undo_changes(other_queue_id, &[history_id]);
+
+        assert!(matches!(result, Err(DatabaseError::HistoryNotFound(id)) if id == history_id));
+        assert!(database
+            .get_messages(&MessageSelector::AllInQueue(queue_id))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn purge_removes_only_history_of_queue() {
+        let (_dir, database) = open_database();
+        let queue_id = database.create_queue("q").unwrap();
+        let other_queue_id = database.create_queue("other").unwrap();
+        database.save_messages(queue_id, &[message("a")]).unwrap();
+        database
+            .save_messages(other_queue_id, &[message("b")])
+            .unwrap();
+        database
+            .delete_messages(&MessageSelector::AllInQueue(queue_id))
+            .unwrap();
+        database
+            .delete_messages(&MessageSelector::AllInQueue(other_queue_id))
+            .unwrap();
+
+        assert_eq!(database.purge_history(queue_id).unwrap(), 1);
+
+        assert!(database.get_deleted_messages(queue_id).unwrap().is_empty());
+        assert_eq!(
+            database.get_deleted_messages(other_queue_id).unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn prune_removes_old_history() {
+        let (_dir, database) = open_database();
+        let queue_id = database.create_queue("q").unwrap();
+        database
+            .save_messages(queue_id, &[message("old"), message("new")])
+            .unwrap();
+        database
+            .delete_messages(&MessageSelector::AllInQueue(queue_id))
+            .unwrap();
+        database
+            .connection
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE message_history SET changed_at = 0 WHERE payload = 'old'",
+                [],
+            )
+            .unwrap();
+
+        assert_eq!(database.prune_history(HISTORY_RETENTION).unwrap(), 1);
+
+        let deleted = database.get_deleted_messages(queue_id).unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].message.content.payload, "new");
+    }
+}
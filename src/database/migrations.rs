@@ -0,0 +1,395 @@
+use super::{DatabaseError, MessageId};
+use log::info;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Connection};
+use serde_json::{Map, Value};
+
+type Migration = fn(&Connection) -> Result<(), DatabaseError>;
+
+/// Steps that upgrade the schema, in order. The schema version stored in `PRAGMA user_version`
+/// is the number of steps applied. Never change or reorder the released steps, add new ones instead.
+/// For the same reason the steps don't use definitions from outside this file, which can change.
+///
+/// Databases created before the versioning was introduced have version 0 and can be at any
+/// of the first steps, so these steps check what already exists
+const MIGRATIONS: [(&str, Migration); 5] = [
+    ("create initial schema", create_initial_schema),
+    ("add delivery columns", add_delivery_columns),
+    ("move properties to columns", move_properties_to_columns),
+    ("create full-text index", create_full_text_index),
+    ("create message history", create_message_history),
+];
+
+/// Columns with the details of how a message was delivered
+const DELIVERY_COLUMNS: [(&str, &str); 4] = [
+    ("payload_encoding", "TEXT NOT NULL DEFAULT 'string'"),
+    ("exchange", "TEXT NOT NULL DEFAULT ''"),
+    ("routing_key", "TEXT NOT NULL DEFAULT ''"),
+    ("redelivered", "INTEGER NOT NULL DEFAULT 0"),
+];
+
+/// Columns of the standard properties as they were added by "move properties to columns".
+/// The names are also the keys of the properties in the management API
+const PROPERTY_COLUMNS: [(&str, &str); 13] = [
+    ("content_type", "TEXT"),
+    ("content_encoding", "TEXT"),
+    ("delivery_mode", "INTEGER"),
+    ("priority", "INTEGER"),
+    ("correlation_id", "TEXT"),
+    ("reply_to", "TEXT"),
+    ("expiration", "TEXT"),
+    ("message_id", "TEXT"),
+    ("timestamp", "INTEGER"),
+    ("type", "TEXT"),
+    ("user_id", "TEXT"),
+    ("app_id", "TEXT"),
+    ("cluster_id", "TEXT"),
+];
+
+/// Applies the steps the database doesn't have yet. Every step runs in its own transaction
+/// together with the version update, so a failed step leaves the database as it was
+pub fn migrate(connection: &mut Connection) -> Result<(), DatabaseError> {
+    let latest_version = MIGRATIONS.len() as u32;
+    let version: u32 = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > latest_version {
+        return Err(DatabaseError::UnsupportedSchemaVersion(
+            version,
+            latest_version,
+        ));
+    }
+
+    for (i, (name, migration)) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let new_version = i as u32 + 1;
+        info!("Upgrading database schema to version {new_version}: {name}");
+
+        let transaction = connection.transaction()?;
+        migration(&transaction)?;
+        transaction.pragma_update(None, "user_version", new_version)?;
+        transaction.commit()?;
+    }
+
+    Ok(())
+}
+
+fn create_initial_schema(connection: &Connection) -> Result<(), DatabaseError> {
+    connection.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS queues (
+            id    INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            name  TEXT NOT NULL,
+            vhost TEXT NOT NULL
+        );
+
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_queue_name_vhost
+        ON queues(name, vhost);
+
+        CREATE TABLE IF NOT EXISTS messages (
+            id        INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            queue_id  TEXT NOT NULL,
+            headers   TEXT NOT NULL,
+            payload   TEXT NOT NULL,
+            FOREIGN KEY(queue_id) REFERENCES queues(id)
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_queue_id
+        ON messages(queue_id);
+        ",
+    )?;
+    Ok(())
+}
+
+fn add_delivery_columns(connection: &Connection) -> Result<(), DatabaseError> {
+    for (column, definition) in DELIVERY_COLUMNS {
+        if !column_exists(connection, "messages", column)? {
+            connection.execute(
+                &format!("ALTER TABLE messages ADD COLUMN {column} {definition}"),
+                (),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Older versions stored the whole property map in the `headers` column.
+/// Moves the standard properties to their own columns and leaves only the application headers
+fn move_properties_to_columns(connection: &Connection) -> Result<(), DatabaseError> {
+    if column_exists(connection, "messages", "content_type")? {
+        return Ok(());
+    }
+
+    for (column, column_type) in PROPERTY_COLUMNS {
+        connection.execute(
+            &format!("ALTER TABLE messages ADD COLUMN {column} {column_type}"),
+            (),
+        )?;
+    }
+
+    let rows = connection
+        .prepare("SELECT id, headers FROM messages")?
+        .query_map([], |row| {
+            Ok((row.get::<_, MessageId>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let assignments = PROPERTY_COLUMNS
+        .map(|(column, _)| format!("{column} = ?"))
+        .join(", ");
+    let mut stmt = connection.prepare(&format!(
+        "UPDATE messages SET headers = ?, {assignments} WHERE id = ?"
+    ))?;
+
+    for (id, legacy_headers) in rows {
+        let mut properties: Map<String, Value> = serde_json::from_str(&legacy_headers)?;
+        let headers = match properties.remove("headers") {
+            Some(Value::Object(headers)) => headers,
+            _ => Map::new(),
+        };
+
+        let mut values = vec![SqlValue::Text(serde_json::to_string(&headers)?)];
+        values
+            .extend(PROPERTY_COLUMNS.map(|(column, _)| legacy_property_value(&properties, column)));
+        values.push(SqlValue::Integer(id.into()));
+        stmt.execute(params_from_iter(values))?;
+    }
+    Ok(())
+}
+
+/// Value of a property from the management API for its column. Values of wrong types
+/// or out of range are dropped
+fn legacy_property_value(properties: &Map<String, Value>, column: &str) -> SqlValue {
+    let value = properties.get(column);
+    let integer = match column {
+        "delivery_mode" => value.and_then(Value::as_i64).filter(|x| matches!(x, 1 | 2)),
+        "priority" => value
+            .and_then(Value::as_i64)
+            .filter(|x| (0..=255).contains(x)),
+        "timestamp" => value.and_then(Value::as_i64),
+        _ => {
+            return match value.and_then(Value::as_str) {
+                Some(s) => SqlValue::Text(s.to_string()),
+                None => SqlValue::Null,
+            };
+        }
+    };
+    integer.map_or(SqlValue::Null, SqlValue::Integer)
+}
+
+/// Full-text index over payloads and headers. It doesn't store the text itself
+/// and is kept in sync with `messages` table by triggers
+fn create_full_text_index(connection: &Connection) -> Result<(), DatabaseError> {
+    connection.execute_batch(
+        "
+        CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts
+        USING fts5(payload, headers, content='messages', content_rowid='id');
+
+        CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages BEGIN
+            INSERT INTO messages_fts(rowid, payload, headers)
+            VALUES (new.id, new.payload, new.headers);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS messages_fts_delete AFTER DELETE ON messages BEGIN
+            INSERT INTO messages_fts(messages_fts, rowid, payload, headers)
+            VALUES ('delete', old.id, old.payload, old.headers);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS messages_fts_update AFTER UPDATE OF payload, headers ON messages BEGIN
+            INSERT INTO messages_fts(messages_fts, rowid, payload, headers)
+            VALUES ('delete', old.id, old.payload, old.headers);
+            INSERT INTO messages_fts(rowid, payload, headers)
+            VALUES (new.id, new.payload, new.headers);
+        END;
+
+        -- index the messages stored before the index was created
+        INSERT INTO messages_fts(messages_fts) VALUES('rebuild');
+        ",
+    )?;
+    Ok(())
+}
+
+/// Previous versions of edited messages and deleted messages.
+/// Has the same columns as `messages`, and `id` is the id of the message
+fn create_message_history(connection: &Connection) -> Result<(), DatabaseError> {
+    connection.execute_batch(
+        "
+        CREATE TABLE message_history (
+            history_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            change     TEXT NOT NULL,
+            changed_at INTEGER NOT NULL,
+            undone     INTEGER NOT NULL DEFAULT 0,
+            id         INTEGER NOT NULL,
+            queue_id   TEXT NOT NULL,
+            headers    TEXT NOT NULL,
+            payload    TEXT NOT NULL,
+            payload_encoding TEXT NOT NULL,
+            content_type     TEXT,
+            content_encoding TEXT,
+            delivery_mode    INTEGER,
+            priority         INTEGER,
+            correlation_id   TEXT,
+            reply_to         TEXT,
+            expiration       TEXT,
+            message_id       TEXT,
+            timestamp        INTEGER,
+            type             TEXT,
+            user_id          TEXT,
+            app_id           TEXT,
+            cluster_id       TEXT,
+            exchange         TEXT NOT NULL,
+            routing_key      TEXT NOT NULL,
+            redelivered      INTEGER NOT NULL
+        );
+
+        CREATE INDEX idx_message_history_id
+        ON message_history(id);
+
+        CREATE INDEX idx_message_history_queue_id
+        ON message_history(queue_id, change);
+        ",
+    )?;
+    Ok(())
+}
+
+fn column_exists(
+    connection: &Connection,
+    table: &str,
+    column: &str,
+) -> Result<bool, DatabaseError> {
+    let exists = connection.query_one(
+        "SELECT count(*) > 0 FROM pragma_table_info(?) WHERE name = ?",
+        [table, column],
+        |row| row.get(0),
+    )?;
+    Ok(exists)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Schema and data as the versions without migrations left them
+    fn open_baseline_database() -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                r#"
+                CREATE TABLE queues (
+                    id    INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    name  TEXT NOT NULL,
+                    vhost TEXT NOT NULL
+                );
+                CREATE UNIQUE INDEX idx_queue_name_vhost ON queues(name, vhost);
+                CREATE TABLE messages (
+                    id        INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    queue_id  TEXT NOT NULL,
+                    headers   TEXT NOT NULL,
+                    payload   TEXT NOT NULL,
+                    FOREIGN KEY(queue_id) REFERENCES queues(id)
+                );
+                CREATE INDEX idx_queue_id ON messages(queue_id);
+
+                INSERT INTO queues (name, vhost) VALUES ('orders', '/');
+                INSERT INTO messages (queue_id, headers, payload) VALUES
+                    (1, '{"content_type": "application/json", "delivery_mode": 2, "priority": 300, "type": "order", "headers": {"x-tenant": "42"}}', 'first'),
+                    (1, '{}', 'second');
+                "#,
+            )
+            .unwrap();
+        connection
+    }
+
+    fn user_version(connection: &Connection) -> u32 {
+        connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    fn dump_messages(connection: &Connection) -> Vec<Vec<SqlValue>> {
+        let mut stmt = connection
+            .prepare("SELECT * FROM messages ORDER BY id")
+            .unwrap();
+        let column_count = stmt.column_count();
+        stmt.query_map([], |row| (0..column_count).map(|i| row.get(i)).collect())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn upgrades_baseline_database_without_losing_rows() {
+        let mut connection = open_baseline_database();
+
+        migrate(&mut connection).unwrap();
+
+        assert_eq!(user_version(&connection), MIGRATIONS.len() as u32);
+        let payloads = connection
+            .prepare("SELECT payload FROM messages ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get::<_, String>(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(payloads, ["first", "second"]);
+    }
+
+    #[test]
+    fn moves_properties_from_headers_to_columns() {
+        let mut connection = open_baseline_database();
+
+        migrate(&mut connection).unwrap();
+
+        let row = connection
+            .query_one(
+                "SELECT headers, content_type, delivery_mode, priority, type, payload_encoding
+                FROM messages WHERE id = 1",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, Option<u8>>(2)?,
+                        row.get::<_, Option<u8>>(3)?,
+                        row.get::<_, Option<String>>(4)?,
+                        row.get::<_, String>(5)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            row,
+            (
+                r#"{"x-tenant":"42"}"#.to_string(),
+                Some("application/json".to_string()),
+                Some(2),
+                // out of range, so it's dropped
+                None,
+                Some("order".to_string()),
+                "string".to_string(),
+            )
+        );
+    }
+
+    #[test]
+    fn second_run_does_nothing() {
+        let mut connection = open_baseline_database();
+        migrate(&mut connection).unwrap();
+        let messages = dump_messages(&connection);
+
+        migrate(&mut connection).unwrap();
+
+        assert_eq!(user_version(&connection), MIGRATIONS.len() as u32);
+        assert_eq!(dump_messages(&connection), messages);
+    }
+
+    #[test]
+    fn rejects_newer_schema() {
+        let mut connection = open_baseline_database();
+        connection
+            .pragma_update(None, "user_version", MIGRATIONS.len() as u32 + 1)
+            .unwrap();
+
+        assert!(matches!(
+            migrate(&mut connection),
+            Err(DatabaseError::UnsupportedSchemaVersion(..))
+        ));
+    }
+}
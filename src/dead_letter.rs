@@ -0,0 +1,204 @@
+//! Replaying dead-lettered messages to the queues they were dead-lettered from,
+//! using the `x-death` header that RabbitMQ adds to them
+use crate::database::MessageId;
+use crate::dtos::{Message, XDeathMode};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+const X_DEATH_HEADER: &str = "x-death";
+/// Where `XDeathMode::Rename` moves the `x-death` entries to
+const X_DEATH_HISTORY_HEADER: &str = "x-death-history";
+/// Summary headers that RabbitMQ adds together with `x-death`
+const X_DEATH_SUMMARY_PREFIXES: [&str; 2] = ["x-first-death-", "x-last-death-"];
+
+#[derive(Error, Debug)]
+pub enum DeadLetterError {
+    #[error("Message {} has no x-death header, it wasn't dead-lettered", .0)]
+    MissingXDeath(MessageId),
+    #[error("Message {message_id} has an invalid x-death header: {reason}")]
+    InvalidXDeath {
+        message_id: MessageId,
+        reason: String,
+    },
+}
+
+/// Returns the queue that the message was dead-lettered from the last time.
+/// RabbitMQ puts the latest event first in `x-death`
+pub fn origin_queue(message: &Message) -> Result<String, DeadLetterError> {
+    let invalid = |reason: &str| DeadLetterError::InvalidXDeath {
+        message_id: message.id,
+        reason: reason.to_string(),
+    };
+
+    let entries = match message.content.headers.get(X_DEATH_HEADER) {
+        None => return Err(DeadLetterError::MissingXDeath(message.id)),
+        Some(Value::Array(entries)) => entries,
+        Some(_) => return Err(invalid("it's not an array")),
+    };
+    let latest = entries.first().ok_or_else(|| invalid("it's empty"))?;
+
+    match latest.get("queue") {
+        Some(Value::String(queue)) if !queue.is_empty() => Ok(queue.clone()),
+        _ => Err(invalid("the latest entry has no queue")),
+    }
+}
+
+/// Groups ids of the messages by their origin queues, keeping the order of messages in each group
+pub fn group_by_origin(
+    messages: &[Message],
+) -> Result<BTreeMap<String, Vec<MessageId>>, DeadLetterError> {
+    let mut groups = BTreeMap::<_, Vec<_>>::new();
+    for message in messages {
+        groups
+            .entry(origin_queue(message)?)
+            .or_default()
+            .push(message.id);
+    }
+    Ok(groups)
+}
+
+impl XDeathMode {
+    /// Changes the dead-lettering headers of a message before it's replayed
+    pub fn apply(self, headers: &mut Map<String, Value>) {
+        match self {
+            XDeathMode::Keep => {}
+            XDeathMode::Strip => {
+                headers.remove(X_DEATH_HEADER);
+                remove_summary_headers(headers);
+            }
+            XDeathMode::Rename => {
+                if let Some(entries) = headers.remove(X_DEATH_HEADER) {
+                    headers.insert(X_DEATH_HISTORY_HEADER.to_string(), entries);
+                }
+                remove_summary_headers(headers);
+            }
+        }
+    }
+}
+
+fn remove_summary_headers(headers: &mut Map<String, Value>) {
+    headers.retain(|name, _| {
+        !X_DEATH_SUMMARY_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message(id: MessageId, headers: Value) -> Message {
+        Message {
+            id,
+            content: serde_json::from_value(json!({ "payload": "", "headers": headers })).unwrap(),
+        }
+    }
+
+    fn dead_lettered(id: MessageId, queues: &[&str]) -> Message {
+        let entries = queues
+            .iter()
+            .map(|queue| json!({ "queue": queue, "reason": "rejected", "count": 1 }))
+            .collect::<Vec<_>>();
+        message(id, json!({ "x-death": entries }))
+    }
+
+    fn headers(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn origin_is_queue_of_latest_entry() {
+        let message = dead_lettered(1, &["retry", "orders"]);
+
+        assert_eq!(origin_queue(&message).unwrap(), "retry");
+    }
+
+    #[test]
+    fn origin_needs_x_death() {
+        let message = message(1, json!({ "other": 1 }));
+
+        assert!(matches!(
+            origin_queue(&message),
+            Err(DeadLetterError::MissingXDeath(1))
+        ));
+    }
+
+    #[test]
+    fn origin_needs_x_death_array() {
+        let message = message(1, json!({ "x-death": "orders" }));
+
+        assert!(matches!(
+            origin_queue(&message),
+            Err(DeadLetterError::InvalidXDeath { message_id: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn origin_needs_x_death_entry() {
+        let message = message(1, json!({ "x-death": [] }));
+
+        assert!(matches!(
+            origin_queue(&message),
+            Err(DeadLetterError::InvalidXDeath { message_id: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn groups_keep_order_of_messages() {
+        let messages = [
+            dead_lettered(1, &["b"]),
+            dead_lettered(2, &["a"]),
+            dead_lettered(3, &["b"]),
+        ];
+
+        let groups = group_by_origin(&messages).unwrap();
+
+        assert_eq!(
+            groups.into_iter().collect::<Vec<_>>(),
+            [("a".to_string(), vec![2]), ("b".to_string(), vec![1, 3])]
+        );
+    }
+
+    #[test]
+    fn strip_removes_dead_lettering_headers() {
+        let mut headers = headers(json!({
+            "x-death": [{ "queue": "orders" }],
+            "x-first-death-queue": "orders",
+            "x-last-death-reason": "rejected",
+            "trace": "kept",
+        }));
+
+        XDeathMode::Strip.apply(&mut headers);
+
+        assert_eq!(Value::Object(headers), json!({ "trace": "kept" }));
+    }
+
+    #[test]
+    fn rename_keeps_entries_in_history() {
+        let mut headers = headers(json!({
+            "x-death": [{ "queue": "orders" }],
+            "x-first-death-queue": "orders",
+            "trace": "kept",
+        }));
+
+        XDeathMode::Rename.apply(&mut headers);
+
+        assert_eq!(
+            Value::Object(headers),
+            json!({ "trace": "kept", "x-death-history": [{ "queue": "orders" }] })
+        );
+    }
+
+    #[test]
+    fn keep_changes_nothing() {
+        let original = headers(json!({ "x-death": [{ "queue": "orders" }] }));
+        let mut headers = original.clone();
+
+        XDeathMode::Keep.apply(&mut headers);
+
+        assert_eq!(headers, original);
+    }
+}
@@ -1,29 +1,365 @@
-use crate::database::{MessageId, QueueId};
+use crate::database::{HistoryId, MessageId, QueueId};
+use crate::jobs::JobId;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 #[derive(Serialize, Clone)]
 pub struct RmqConnectionInfo {
+    /// Identifies the connection in routes `/api/connections/{name}/...`
+    pub name: String,
     pub domain: String,
     pub server_name: Option<String>,
     pub vhost: String,
+    pub importance_level: u8,
 }
 
 #[derive(Serialize, Clone)]
 pub struct EnvInfo {
-    pub rmq_connection_info: RmqConnectionInfo,
-    pub importance_level: u8,
+    pub connections: Vec<RmqConnectionInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadEncoding {
+    #[default]
+    String,
+    Base64,
+}
+
+impl PayloadEncoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PayloadEncoding::String => "string",
+            PayloadEncoding::Base64 => "base64",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "string" => Some(PayloadEncoding::String),
+            "base64" => Some(PayloadEncoding::Base64),
+            _ => None,
+        }
+    }
+}
+
+/// Standard AMQP 0-9-1 basic properties. Application headers are kept separately.
+/// Properties that aren't set are omitted in JSON
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq)]
+pub struct MessageProperties {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivery_mode: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub message_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster_id: Option<String>,
+}
+
+impl MessageProperties {
+    /// Splits a property map in the format of RabbitMQ management API into
+    /// the standard properties and the application headers
+    pub fn from_rmq_properties(mut properties: Map<String, Value>) -> (Self, Map<String, Value>) {
+        let headers = match properties.remove("headers") {
+            Some(Value::Object(headers)) => headers,
+            _ => Map::new(),
+        };
+
+        let string = |key| {
+            properties
+                .get(key)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        };
+        // values out of range are dropped rather than truncated
+        let byte = |key| {
+            properties
+                .get(key)
+                .and_then(Value::as_u64)
+                .and_then(|x| u8::try_from(x).ok())
+        };
+
+        let message_properties = Self {
+            content_type: string("content_type"),
+            content_encoding: string("content_encoding"),
+            delivery_mode: byte("delivery_mode").filter(|x| matches!(x, 1 | 2)),
+            priority: byte("priority"),
+            correlation_id: string("correlation_id"),
+            reply_to: string("reply_to"),
+            expiration: string("expiration"),
+            message_id: string("message_id"),
+            timestamp: properties.get("timestamp").and_then(Value::as_i64),
+            message_type: string("type"),
+            user_id: string("user_id"),
+            app_id: string("app_id"),
+            cluster_id: string("cluster_id"),
+        };
+
+        (message_properties, headers)
+    }
+
+    /// Builds a property map in the format of RabbitMQ management API
+    pub fn to_rmq_properties(&self, headers: &Map<String, Value>) -> Map<String, Value> {
+        let mut properties = match serde_json::to_value(self) {
+            Ok(Value::Object(properties)) => properties,
+            _ => unreachable!("MessageProperties is always serialized to an object"),
+        };
+        properties.retain(|_, value| !value.is_null());
+
+        if !headers.is_empty() {
+            properties.insert("headers".to_string(), Value::Object(headers.clone()));
+        }
+
+        properties
+    }
+}
+
+/// Everything RabbitMQ knows about a message
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MessageContent {
+    pub payload: String,
+    #[serde(default)]
+    pub payload_encoding: PayloadEncoding,
+    #[serde(default)]
+    pub properties: MessageProperties,
+    #[serde(default)]
+    pub headers: Map<String, Value>,
+    /// Exchange the message was originally published to
+    #[serde(default)]
+    pub exchange: String,
+    /// Routing key the message was originally published with
+    #[serde(default)]
+    pub routing_key: String,
+    #[serde(default)]
+    pub redelivered: bool,
 }
 
 #[derive(Serialize)]
 pub struct Message {
     pub id: MessageId,
-    pub payload: String,
-    pub headers: serde_json::Map<String, serde_json::Value>,
+    #[serde(flatten)]
+    pub content: MessageContent,
 }
 
 #[derive(Deserialize)]
 pub struct LoadMessagesByQueueNameQuery {
     pub queue_name: String,
+    /// Load at most this many messages
+    pub max_count: Option<u64>,
+    #[serde(flatten)]
+    pub filter: FilterCriteria,
+}
+
+#[derive(Deserialize)]
+pub struct MoveMessagesQuery {
+    pub source_queue_name: String,
+    pub destination_queue_name: String,
+    /// Move at most this many messages
+    pub max_count: Option<u64>,
+    #[serde(flatten)]
+    pub filter: FilterCriteria,
+}
+
+/// Selects messages by their content. All given criteria must match
+#[derive(Deserialize, Default)]
+pub struct FilterCriteria {
+    /// Only messages whose payload contains the substring
+    pub payload_contains: Option<String>,
+    /// Only messages with a JSON payload that has a node at the JSONPath
+    pub json_path: Option<String>,
+    /// Only messages that have the header
+    pub header_name: Option<String>,
+    /// Only messages whose `header_name` header has the value
+    pub header_value: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Deserialize, Default)]
+pub struct MessagesPageQuery {
+    #[serde(default)]
+    pub offset: u64,
+    /// All messages starting from `offset` are returned when not set
+    pub limit: Option<u64>,
+    /// Messages are sorted by id, i.e. in the order they were loaded
+    #[serde(default)]
+    pub sort: SortOrder,
+    /// Case-insensitive text to find in payloads and headers
+    pub search: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct MessagesPage {
+    pub messages: Vec<Message>,
+    /// Number of messages matching the search, regardless of `offset` and `limit`
+    pub total: u32,
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    /// Words to find in payloads and headers
+    pub q: String,
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct SearchHit {
+    pub queue_id: QueueId,
+    pub queue_name: String,
+    pub message_id: MessageId,
+    /// Parts of the payload around the found words, which are wrapped in `<mark>` tags.
+    /// The message text is HTML-escaped
+    pub payload_snippet: String,
+    pub headers_snippet: String,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    Edit,
+    Delete,
+}
+
+impl Change {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Change::Edit => "edit",
+            Change::Delete => "delete",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "edit" => Some(Change::Edit),
+            "delete" => Some(Change::Delete),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct HistoryEntry {
+    pub history_id: HistoryId,
+    pub change: Change,
+    /// Unix time in milliseconds
+    pub changed_at: i64,
+    pub undone: bool,
+    /// The message as it was before the change
+    pub message: Message,
+}
+
+#[derive(Deserialize)]
+pub struct UndoChangesRequest {
+    pub history_ids: Vec<HistoryId>,
+}
+
+#[derive(Serialize)]
+pub struct UndoChangesResult {
+    pub undone: usize,
+}
+
+#[derive(Serialize)]
+pub struct PurgeHistoryResult {
+    pub purged: usize,
+}
+
+/// Replaces all properties and headers of a message
+#[derive(Deserialize)]
+pub struct UpdateMessageMetadataRequest {
+    #[serde(default)]
+    pub properties: Map<String, Value>,
+    #[serde(default)]
+    pub headers: Map<String, Value>,
+}
+
+/// Sets or removes one header on many messages
+#[derive(Deserialize)]
+pub struct SetHeaderRequest {
+    /// All messages of the queue when empty
+    #[serde(default)]
+    pub message_ids: Vec<MessageId>,
+    pub name: String,
+    /// The header is removed when not set
+    pub value: Option<Value>,
+}
+
+#[derive(Serialize)]
+pub struct SetHeaderResult {
+    pub updated: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TransformationRequest {
+    /// RFC 6902 JSON Patch operations
+    JsonPatch { patch: Value },
+    RegexReplace {
+        pattern: String,
+        replacement: String,
+    },
+}
+
+#[derive(Deserialize)]
+pub struct TransformMessagesRequest {
+    /// All messages of the queue when empty
+    #[serde(default)]
+    pub message_ids: Vec<MessageId>,
+    pub transformation: TransformationRequest,
+    /// Only shows what would change
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize)]
+pub struct TransformMessagesResult {
+    /// Payloads are changed only if no message failed and it isn't a dry run
+    pub applied: bool,
+    pub changed: Vec<TransformPreview>,
+    pub unchanged: usize,
+    pub failed: Vec<TransformFailure>,
+}
+
+#[derive(Serialize)]
+pub struct TransformPreview {
+    pub message_id: MessageId,
+    /// Unified diff of the payload
+    pub diff: String,
+}
+
+#[derive(Serialize)]
+pub struct TransformFailure {
+    pub message_id: MessageId,
+    pub error: String,
+}
+
+#[derive(Serialize)]
+pub struct ImportMessagesResult {
+    pub imported: usize,
 }
 
 #[derive(Deserialize)]
@@ -32,9 +368,23 @@ pub struct PeekMessagesQuery {
 }
 
 #[derive(Serialize)]
-pub struct LoadMessagesByQueueNameResponse {
+pub struct LoadMessagesResult {
     pub queue_id: QueueId,
-    pub messages: Vec<Message>,
+    pub loaded: u64,
+    /// Messages that didn't match the filter and were published back to the queue
+    pub returned: u64,
+}
+
+#[derive(Serialize)]
+pub struct MoveMessagesResult {
+    pub moved: u64,
+    /// Messages that didn't match the filter and were published back to the source queue
+    pub returned: u64,
+}
+
+#[derive(Serialize)]
+pub struct PublishMessagesResult {
+    pub published: u64,
 }
 
 #[derive(Serialize)]
@@ -51,15 +401,160 @@ pub struct DeleteMessagesRequest {
     pub message_ids: Vec<MessageId>,
 }
 
+#[derive(Serialize)]
+pub struct DeleteMessagesResult {
+    pub deleted: usize,
+}
+
 #[derive(Deserialize)]
 pub struct SendMessagesRequest {
     pub message_ids: Vec<MessageId>,
+    #[serde(default)]
     pub destination_queue_name: String,
+    /// Exchange to publish to instead of `destination_queue_name`.
+    /// Can be a template with placeholders, see `RoutingTemplate`
+    pub exchange: Option<String>,
+    /// Routing key used together with `exchange`. Can be a template with placeholders
+    pub routing_key: Option<String>,
+    /// Publish every message to the exchange and routing key it was originally published with
+    /// instead of `destination_queue_name`
+    #[serde(default)]
+    pub to_original_destination: bool,
+    /// Publish every message to the queue it was dead-lettered from, taken from its `x-death`
+    /// header, instead of `destination_queue_name`
+    #[serde(default)]
+    pub to_dead_letter_origin: bool,
+    /// What to do with `x-death` headers when `to_dead_letter_origin` is set
+    #[serde(default)]
+    pub x_death: XDeathMode,
+    /// Remove a message from the database only after the broker has confirmed it, and publish
+    /// it as persistent. Needs the connection to use AMQP
+    #[serde(default)]
+    pub confirmed: bool,
+    #[serde(default)]
     pub send_delay_ms: u64,
 }
 
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum XDeathMode {
+    #[default]
+    Keep,
+    /// Remove `x-death` and the `x-first-death-*` and `x-last-death-*` headers,
+    /// so that consumers that count retries by them start over
+    Strip,
+    /// Like `Strip`, but keep the entries in the `x-death-history` header
+    Rename,
+}
+
+#[derive(Serialize)]
+pub struct SendMessagesResponse {
+    /// Messages that were routed to at least one queue. Each one is removed from the database
+    /// as soon as it's published, or confirmed by the broker in the confirmed mode
+    pub routed: Vec<MessageId>,
+    /// Messages that weren't routed to any queue. They stay in the database
+    pub unrouted: Vec<MessageId>,
+}
+
+#[derive(Serialize)]
+pub struct ExchangeSummary {
+    pub name: String,
+    pub exchange_type: String,
+}
+
+#[derive(Serialize)]
+pub struct VhostSummary {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct VhostQuery {
+    /// The vhost of the connection is used if it isn't set
+    pub vhost: Option<String>,
+}
+
 #[derive(Serialize)]
 pub struct QueueCounters {
     pub queue_name: String,
     pub messages: u64,
 }
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Load,
+    Send,
+    Delete,
+    Move,
+    Publish,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Paused,
+    Cancelled,
+    Completed,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn is_active(&self) -> bool {
+        matches!(self, JobStatus::Running | JobStatus::Paused)
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct JobInfo {
+    pub id: JobId,
+    pub kind: JobKind,
+    pub description: String,
+    pub status: JobStatus,
+    pub processed: u64,
+    pub total: Option<u64>,
+    pub error: Option<String>,
+    /// Result of a completed job, its shape depends on the kind of the job
+    pub result: Option<Value>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum WebsocketMessage<'a> {
+    QueueCounters(&'a [QueueCounters]),
+    Job(&'a JobInfo),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn from_rmq_properties_drops_values_out_of_range() {
+        let properties = json!({"delivery_mode": 258, "priority": 300, "headers": {"a": 1}});
+        let Value::Object(properties) = properties else {
+            unreachable!()
+        };
+
+        let (properties, headers) = MessageProperties::from_rmq_properties(properties);
+
+        assert_eq!(properties.delivery_mode, None);
+        assert_eq!(properties.priority, None);
+        assert_eq!(headers, json!({"a": 1}).as_object().unwrap().clone());
+    }
+
+    #[test]
+    fn from_rmq_properties_keeps_valid_values() {
+        let properties = json!({"delivery_mode": 2, "priority": 255});
+        let Value::Object(properties) = properties else {
+            unreachable!()
+        };
+
+        let (properties, _) = MessageProperties::from_rmq_properties(properties);
+
+        assert_eq!(properties.delivery_mode, Some(2));
+        assert_eq!(properties.priority, Some(255));
+    }
+}
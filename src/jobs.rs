@@ -0,0 +1,359 @@
+use crate::dtos::{JobInfo, JobKind, JobStatus};
+use log::{debug, error, info};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::{broadcast, watch};
+
+pub type JobId = u32;
+
+/// Minimal interval between two progress notifications of the same job
+const PROGRESS_NOTIFICATION_INTERVAL: Duration = Duration::from_millis(250);
+/// Finished jobs that are kept with their results, the oldest ones are removed first
+const MAX_FINISHED_JOBS: usize = 100;
+
+/// Registry of long-running operations that run in background tasks.
+/// Every change of a job is broadcast to subscribers
+#[derive(Clone)]
+pub struct Jobs {
+    inner: Arc<Mutex<JobsInner>>,
+    updates: broadcast::Sender<JobInfo>,
+}
+
+struct JobsInner {
+    next_id: JobId,
+    jobs: BTreeMap<JobId, JobEntry>,
+}
+
+struct JobEntry {
+    info: JobInfo,
+    control: watch::Sender<JobControl>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum JobControl {
+    Run,
+    Pause,
+    Cancel,
+}
+
+#[derive(Error, Debug)]
+pub enum JobError {
+    #[error("Job {} not found", .0)]
+    NotFound(JobId),
+    #[error("Job {} is {:?} and can't be {}", .0, .1, .2)]
+    InvalidState(JobId, JobStatus, &'static str),
+}
+
+#[derive(Error, Debug)]
+#[error("Job was cancelled")]
+pub struct JobCancelled;
+
+/// Given to a job task to report progress and to react to pause and cancel requests
+pub struct JobHandle {
+    id: JobId,
+    jobs: Jobs,
+    control: watch::Receiver<JobControl>,
+    last_notification: Instant,
+}
+
+impl Jobs {
+    pub fn new() -> Self {
+        let (updates, _) = broadcast::channel(1024);
+        Self {
+            inner: Arc::new(Mutex::new(JobsInner {
+                next_id: 1,
+                jobs: BTreeMap::new(),
+            })),
+            updates,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<JobInfo> {
+        self.updates.subscribe()
+    }
+
+    pub fn list(&self) -> Vec<JobInfo> {
+        let inner = self.inner.lock().unwrap();
+        inner.jobs.values().map(|x| x.info.clone()).collect()
+    }
+
+    pub fn get(&self, id: JobId) -> Result<JobInfo, JobError> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .jobs
+            .get(&id)
+            .map(|x| x.info.clone())
+            .ok_or(JobError::NotFound(id))
+    }
+
+    /// Starts the job in a background task. The task must call `JobHandle::checkpoint` regularly
+    /// so that it can be paused and cancelled
+    pub fn spawn<F, Fut, E>(&self, kind: JobKind, description: String, f: F) -> JobInfo
+    where
+        F: FnOnce(JobHandle) -> Fut,
+        Fut: Future<Output = Result<Value, E>> + Send + 'static,
+        E: Display,
+    {
+        let (control_sender, control_receiver) = watch::channel(JobControl::Run);
+
+        let info = {
+            let mut inner = self.inner.lock().unwrap();
+            let id = inner.next_id;
+            inner.next_id += 1;
+
+            let info = JobInfo {
+                id,
+                kind,
+                description,
+                status: JobStatus::Running,
+                processed: 0,
+                total: None,
+                error: None,
+                result: None,
+            };
+            inner.jobs.insert(
+                id,
+                JobEntry {
+                    info: info.clone(),
+                    control: control_sender,
+                },
+            );
+            info
+        };
+
+        info!("Started job {}: {}", info.id, info.description);
+        self.notify(info.clone());
+
+        let handle = JobHandle {
+            id: info.id,
+            jobs: self.clone(),
+            control: control_receiver,
+            last_notification: Instant::now(),
+        };
+        let job = f(handle);
+        let jobs = self.clone();
+        let id = info.id;
+
+        tokio::spawn(async move {
+            let result = job.await;
+            jobs.finish(id, result);
+        });
+
+        info
+    }
+
+    /// Removes a finished job with its result
+    pub fn remove(&self, id: JobId) -> Result<JobInfo, JobError> {
+        let mut inner = self.inner.lock().unwrap();
+        let entry = inner.jobs.get(&id).ok_or(JobError::NotFound(id))?;
+        if entry.info.status.is_active() {
+            return Err(JobError::InvalidState(id, entry.info.status, "removed"));
+        }
+        let entry = inner.jobs.remove(&id).expect("Job exists");
+        Ok(entry.info)
+    }
+
+    pub fn pause(&self, id: JobId) -> Result<JobInfo, JobError> {
+        self.control(id, "paused", JobStatus::Running, JobControl::Pause)
+    }
+
+    pub fn resume(&self, id: JobId) -> Result<JobInfo, JobError> {
+        self.control(id, "resumed", JobStatus::Paused, JobControl::Run)
+    }
+
+    pub fn cancel(&self, id: JobId) -> Result<JobInfo, JobError> {
+        let info = {
+            let mut inner = self.inner.lock().unwrap();
+            let entry = inner.jobs.get_mut(&id).ok_or(JobError::NotFound(id))?;
+            if !entry.info.status.is_active() {
+                return Err(JobError::InvalidState(id, entry.info.status, "cancelled"));
+            }
+            entry.control.send_replace(JobControl::Cancel);
+            entry.info.clone()
+        };
+
+        info!("Cancelling job {}", id);
+        Ok(info)
+    }
+
+    fn control(
+        &self,
+        id: JobId,
+        action: &'static str,
+        expected_status: JobStatus,
+        control: JobControl,
+    ) -> Result<JobInfo, JobError> {
+        let info = {
+            let mut inner = self.inner.lock().unwrap();
+            let entry = inner.jobs.get_mut(&id).ok_or(JobError::NotFound(id))?;
+            if entry.info.status != expected_status || *entry.control.borrow() == JobControl::Cancel
+            {
+                return Err(JobError::InvalidState(id, entry.info.status, action));
+            }
+            entry.control.send_replace(control);
+            entry.info.status = match control {
+                JobControl::Pause => JobStatus::Paused,
+                _ => JobStatus::Running,
+            };
+            entry.info.clone()
+        };
+
+        info!("Job {} is {}", id, action);
+        self.notify(info.clone());
+        Ok(info)
+    }
+
+    fn update(&self, id: JobId, f: impl FnOnce(&mut JobInfo)) -> Option<JobInfo> {
+        let mut inner = self.inner.lock().unwrap();
+        let entry = inner.jobs.get_mut(&id)?;
+        f(&mut entry.info);
+        Some(entry.info.clone())
+    }
+
+    fn finish<E: Display>(&self, id: JobId, result: Result<Value, E>) {
+        let info = self.update(id, |info| {
+            match result {
+                Ok(result) => {
+                    info.status = JobStatus::Completed;
+                    info.result = Some(result);
+                }
+                Err(_) if info.status == JobStatus::Cancelled => {}
+                Err(e) => {
+                    info.status = JobStatus::Failed;
+                    info.error = Some(e.to_string());
+                }
+            };
+        });
+        self.remove_old_finished_jobs();
+
+        if let Some(info) = info {
+            match info.status {
+                JobStatus::Failed => error!(
+                    "Job {} failed: {}",
+                    id,
+                    info.error.as_deref().unwrap_or_default()
+                ),
+                status => info!("Job {} is {:?}", id, status),
+            }
+            self.notify(info);
+        }
+    }
+
+    fn remove_old_finished_jobs(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        let finished = inner
+            .jobs
+            .values()
+            .filter(|x| !x.info.status.is_active())
+            .map(|x| x.info.id)
+            .collect::<Vec<_>>();
+
+        // ids grow, so the first ones are the oldest
+        let excess = finished.len().saturating_sub(MAX_FINISHED_JOBS);
+        for id in &finished[..excess] {
+            inner.jobs.remove(id);
+        }
+    }
+
+    fn notify(&self, info: JobInfo) {
+        // an error only means that nobody listens at the moment
+        if self.updates.send(info).is_err() {
+            debug!("No subscribers for job updates");
+        }
+    }
+}
+
+impl JobHandle {
+    pub fn set_total(&mut self, total: u64) {
+        if let Some(info) = self.jobs.update(self.id, |info| info.total = Some(total)) {
+            self.last_notification = Instant::now();
+            self.jobs.notify(info);
+        }
+    }
+
+    pub fn set_processed(&mut self, processed: u64) {
+        let info = self.jobs.update(self.id, |info| info.processed = processed);
+
+        if let Some(info) = info {
+            let finished = info.total == Some(processed);
+            if finished || self.last_notification.elapsed() >= PROGRESS_NOTIFICATION_INTERVAL {
+                self.last_notification = Instant::now();
+                self.jobs.notify(info);
+            }
+        }
+    }
+
+    /// Waits while the job is paused. Returns an error if the job was cancelled,
+    /// in which case the task should stop as soon as possible
+    pub async fn checkpoint(&mut self) -> Result<(), JobCancelled> {
+        loop {
+            let control = *self.control.borrow_and_update();
+            match control {
+                JobControl::Run => return Ok(()),
+                JobControl::Cancel => {
+                    self.jobs
+                        .update(self.id, |info| info.status = JobStatus::Cancelled);
+                    return Err(JobCancelled);
+                }
+                JobControl::Pause => {
+                    if self.control.changed().await.is_err() {
+                        return Err(JobCancelled);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn run_job(jobs: &Jobs) -> JobId {
+        let mut updates = jobs.subscribe();
+        let info = jobs.spawn(JobKind::Delete, "test".to_string(), |_| async {
+            Ok::<_, JobCancelled>(Value::Null)
+        });
+        while updates.recv().await.unwrap().status.is_active() {}
+        info.id
+    }
+
+    #[tokio::test]
+    async fn keeps_limited_number_of_finished_jobs() {
+        let jobs = Jobs::new();
+        for _ in 0..MAX_FINISHED_JOBS + 5 {
+            run_job(&jobs).await;
+        }
+
+        let ids = jobs.list().iter().map(|x| x.id).collect::<Vec<_>>();
+        assert_eq!(ids.len(), MAX_FINISHED_JOBS);
+        assert_eq!(ids[0], 6);
+    }
+
+    #[tokio::test]
+    async fn removes_finished_job() {
+        let jobs = Jobs::new();
+        let id = run_job(&jobs).await;
+
+        jobs.remove(id).unwrap();
+
+        assert!(matches!(jobs.get(id), Err(JobError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn does_not_remove_running_job() {
+        let jobs = Jobs::new();
+        let info = jobs.spawn(JobKind::Delete, "test".to_string(), |_| {
+            std::future::pending::<Result<Value, JobCancelled>>()
+        });
+
+        assert!(matches!(
+            jobs.remove(info.id),
+            Err(JobError::InvalidState(..))
+        ));
+    }
+}
@@ -1,58 +1,93 @@
 mod api;
 mod args;
 mod check_version;
+mod cli;
+mod config;
 mod database;
+mod dead_letter;
 mod dtos;
+mod jobs;
+mod message_filter;
+mod message_metadata;
+mod ndjson;
 mod rabbitmq;
 mod rmq_background;
+mod routing_template;
+mod transform;
+mod transport;
 mod types;
 
 use crate::args::Args;
 use crate::check_version::show_notification_if_new_version_available;
+use crate::config::Settings;
 use crate::database::Database;
 use crate::rabbitmq::Rabbitmq;
 use crate::rmq_background::RmqBackground;
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use clap::Parser;
 use log::{error, info, LevelFilter};
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::process::ExitCode;
 use std::sync::Arc;
 use std::time::Duration;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     env_logger::builder()
         .filter_level(LevelFilter::Info)
         .format_timestamp(None)
         .format_target(false)
         .init();
 
-    _ = run().await.inspect_err(|e| error!("{:?}", e));
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{:?}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
 
 async fn run() -> Result<()> {
     let args = Args::parse();
+    let settings = Settings::resolve(&args)?;
+
+    if let Some(command) = args.command {
+        let [settings] = &settings[..] else {
+            return Err(anyhow!("Commands work with exactly one profile"));
+        };
+        let rmq_client = Arc::new(connect(settings).await?);
+        return cli::run_command(command, rmq_client).await;
+    }
+
     show_notification_if_new_version_available().await;
 
-    let rmq_client =
-        Arc::new(Rabbitmq::connect(&args.url, &args.vhost, args.show_exclusive_queues).await?);
-    let rmq_background = RmqBackground::new(
-        Arc::clone(&rmq_client),
-        Duration::from_secs(args.update_interval as u64),
-    );
-    let connection_info = rmq_client.get_connection_info();
-    let database = Database::new(&connection_info.domain, &connection_info.vhost)?;
+    let mut connections = vec![];
+    for settings in settings {
+        let rmq_client = Arc::new(connect(&settings).await?);
+        let update_interval = Duration::from_secs(settings.update_interval as u64);
+        let rmq_background = RmqBackground::new(Arc::clone(&rmq_client), update_interval);
+        let connection_info = rmq_client.get_connection_info();
+        let database = Database::open(
+            &rmq_client.database_name(),
+            &connection_info.domain,
+            &connection_info.vhost,
+        )?;
+
+        connections.push(api::Connection {
+            name: settings.name,
+            server_name: settings.server_name,
+            importance_level: settings.importance_level,
+            rabbitmq: rmq_client,
+            database,
+            rmq_background,
+            update_interval,
+        });
+    }
     let wwwroot_dir = get_wwwroot_directory()?;
 
-    let app = api::build_api(
-        rmq_client,
-        args.server_name,
-        args.importance_level,
-        database,
-        rmq_background,
-        wwwroot_dir,
-    );
+    let app = api::build_api(connections, wwwroot_dir);
 
     info!(
         "Web interface is on http://rmq-tools.localhost:{}",
@@ -68,6 +103,18 @@ async fn run() -> Result<()> {
     Ok(())
 }
 
+async fn connect(settings: &Settings) -> Result<Rabbitmq> {
+    Rabbitmq::connect(
+        &settings.url,
+        &settings.credentials,
+        &settings.vhost,
+        settings.show_exclusive_queues,
+        settings.amqp_url.as_ref(),
+    )
+    .await
+    .with_context(|| format!("Connection '{}' failed", settings.name))
+}
+
 fn get_wwwroot_directory() -> Result<PathBuf> {
     const WWWROOT_DIR: &str = "wwwroot";
 
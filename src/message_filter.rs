@@ -0,0 +1,89 @@
+use crate::dtos::{FilterCriteria, MessageContent, PayloadEncoding};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::Value;
+use serde_json_path::JsonPath;
+use thiserror::Error;
+
+/// Predicate that selects which messages are loaded from a queue.
+/// A message matches when it satisfies every given criterion
+pub struct MessageFilter {
+    criteria: Vec<Criterion>,
+}
+
+enum Criterion {
+    PayloadContains(String),
+    /// The JSON payload has at least one node at the path
+    JsonPath(JsonPath),
+    /// The header is present and, if a value is given, equal to it
+    Header {
+        name: String,
+        value: Option<String>,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum FilterError {
+    #[error("JSONPath '{path}' is invalid: {reason}")]
+    InvalidJsonPath { path: String, reason: String },
+    #[error("Header value is given without a header name")]
+    HeaderValueWithoutName,
+}
+
+impl MessageFilter {
+    /// Returns `None` when no criteria are given
+    pub fn from_criteria(request: &FilterCriteria) -> Result<Option<Self>, FilterError> {
+        let mut criteria = vec![];
+
+        if let Some(substring) = &request.payload_contains {
+            criteria.push(Criterion::PayloadContains(substring.clone()));
+        }
+
+        if let Some(path) = &request.json_path {
+            let path = JsonPath::parse(path).map_err(|e| FilterError::InvalidJsonPath {
+                path: path.clone(),
+                reason: e.to_string(),
+            })?;
+            criteria.push(Criterion::JsonPath(path));
+        }
+
+        match (&request.header_name, &request.header_value) {
+            (Some(name), value) => criteria.push(Criterion::Header {
+                name: name.clone(),
+                value: value.clone(),
+            }),
+            (None, Some(_)) => return Err(FilterError::HeaderValueWithoutName),
+            (None, None) => {}
+        }
+
+        Ok((!criteria.is_empty()).then_some(Self { criteria }))
+    }
+
+    pub fn matches(&self, message: &MessageContent) -> bool {
+        let payload = decoded_payload(message);
+        self.criteria.iter().all(|criterion| match criterion {
+            Criterion::PayloadContains(substring) => payload.contains(substring.as_str()),
+            Criterion::JsonPath(path) => serde_json::from_str::<Value>(&payload)
+                .is_ok_and(|json| !path.query(&json).is_empty()),
+            Criterion::Header { name, value } => match (message.headers.get(name), value) {
+                (None, _) => false,
+                (Some(_), None) => true,
+                (Some(Value::String(s)), Some(value)) => s == value,
+                // numbers and booleans are compared by their JSON representation
+                (Some(other), Some(value)) => {
+                    serde_json::from_str::<Value>(value).is_ok_and(|value| value == *other)
+                }
+            },
+        })
+    }
+}
+
+fn decoded_payload(message: &MessageContent) -> String {
+    match message.payload_encoding {
+        PayloadEncoding::String => message.payload.clone(),
+        PayloadEncoding::Base64 => match STANDARD.decode(&message.payload) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(_) => message.payload.clone(),
+        },
+    }
+}
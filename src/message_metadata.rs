@@ -0,0 +1,97 @@
+use crate::dtos::MessageProperties;
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+/// AMQP short strings, which include property values and header names, are limited to 255 bytes
+const MAX_SHORT_STRING_LEN: usize = 255;
+
+#[derive(Error, Debug)]
+pub enum MetadataError {
+    #[error("Unknown property '{}'", .0)]
+    UnknownProperty(String),
+    #[error("Property '{name}' {reason}")]
+    InvalidProperty { name: String, reason: &'static str },
+    #[error("Header '{name}' {reason}")]
+    InvalidHeader { name: String, reason: &'static str },
+}
+
+/// Checks that the values have the types of AMQP 0-9-1 basic properties.
+/// Properties that are missing or null aren't set
+pub fn parse_properties(
+    properties: Map<String, Value>,
+) -> Result<MessageProperties, MetadataError> {
+    for (name, value) in &properties {
+        let invalid = |reason| MetadataError::InvalidProperty {
+            name: name.clone(),
+            reason,
+        };
+
+        match (name.as_str(), value) {
+            (_, Value::Null) => {}
+            ("delivery_mode", value) => match value.as_u64() {
+                Some(1 | 2) => {}
+                _ => return Err(invalid("must be 1 (non-persistent) or 2 (persistent)")),
+            },
+            ("priority", value) => match value.as_u64() {
+                Some(0..=255) => {}
+                _ => return Err(invalid("must be an integer from 0 to 255")),
+            },
+            ("timestamp", value) => {
+                if value.as_i64().is_none_or(|x| x < 0) {
+                    return Err(invalid("must be a non-negative integer of seconds"));
+                }
+            }
+            ("expiration", value) => match value.as_str() {
+                Some(s) if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) => {}
+                _ => return Err(invalid("must be a string with a non-negative integer")),
+            },
+            (
+                "content_type" | "content_encoding" | "correlation_id" | "reply_to" | "message_id"
+                | "type" | "user_id" | "app_id" | "cluster_id",
+                value,
+            ) => match value.as_str() {
+                Some(s) if s.len() <= MAX_SHORT_STRING_LEN => {}
+                Some(_) => return Err(invalid("must not be longer than 255 bytes")),
+                None => return Err(invalid("must be a string")),
+            },
+            ("headers", _) => return Err(invalid("must be given separately from properties")),
+            (name, _) => return Err(MetadataError::UnknownProperty(name.to_string())),
+        }
+    }
+
+    Ok(serde_json::from_value(Value::Object(properties))
+        .expect("Validated properties are always deserializable"))
+}
+
+/// Checks that the headers can be sent as an AMQP field table
+pub fn validate_headers(headers: &Map<String, Value>) -> Result<(), MetadataError> {
+    for (name, value) in headers {
+        validate_header_name(name)?;
+        validate_header_value(value)?;
+    }
+    Ok(())
+}
+
+pub fn validate_header_name(name: &str) -> Result<(), MetadataError> {
+    let invalid = |reason| MetadataError::InvalidHeader {
+        name: name.to_string(),
+        reason,
+    };
+
+    if name.is_empty() {
+        return Err(invalid("must have a name"));
+    }
+    if name.len() > MAX_SHORT_STRING_LEN {
+        return Err(invalid("name must not be longer than 255 bytes"));
+    }
+    Ok(())
+}
+
+/// Null values are allowed and sent as AMQP void
+pub fn validate_header_value(value: &Value) -> Result<(), MetadataError> {
+    match value {
+        Value::Array(values) => values.iter().try_for_each(validate_header_value),
+        Value::Object(table) => validate_headers(table),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,77 @@
+//! Message dumps in NDJSON format: one JSON object per line, with the same fields
+//! as `MessageContent`. Only `payload` is required when importing
+use crate::dtos::{MessageContent, PayloadEncoding};
+use crate::message_metadata;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::Value;
+use thiserror::Error;
+
+pub const CONTENT_TYPE: &str = "application/x-ndjson";
+
+#[derive(Error, Debug)]
+#[error("Line {line}: {reason}")]
+pub struct ImportError {
+    line: usize,
+    reason: String,
+}
+
+pub fn export_messages<'a>(messages: impl IntoIterator<Item = &'a MessageContent>) -> String {
+    let mut result = String::new();
+    for message in messages {
+        let line = serde_json::to_string(message).expect("MessageContent is always serializable");
+        result.push_str(&line);
+        result.push('\n');
+    }
+    result
+}
+
+/// Parses and validates all lines. Empty lines are skipped
+pub fn import_messages(ndjson: &str) -> Result<Vec<MessageContent>, ImportError> {
+    let mut messages = vec![];
+
+    for (i, line) in ndjson.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let error = |reason: String| ImportError {
+            line: i + 1,
+            reason,
+        };
+
+        let json: Value = serde_json::from_str(line).map_err(|e| error(e.to_string()))?;
+        if let Some(Value::Object(properties)) = json.get("properties") {
+            message_metadata::parse_properties(properties.clone())
+                .map_err(|e| error(e.to_string()))?;
+        }
+        let message: MessageContent =
+            serde_json::from_value(json).map_err(|e| error(e.to_string()))?;
+        message_metadata::validate_headers(&message.headers).map_err(|e| error(e.to_string()))?;
+        if message.payload_encoding == PayloadEncoding::Base64
+            && let Err(e) = STANDARD.decode(&message.payload)
+        {
+            return Err(error(format!("payload isn't valid base64: {e}")));
+        }
+
+        messages.push(message);
+    }
+
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn null_headers_survive_export_and_import() {
+        let line = r#"{"payload":"x","headers":{"x-null":null,"x-list":[null]}}"#;
+        let messages = import_messages(line).unwrap();
+
+        let exported = export_messages(&messages);
+        let imported = import_messages(&exported).unwrap();
+
+        assert_eq!(imported[0].headers["x-null"], Value::Null);
+        assert_eq!(imported[0].headers["x-list"][0], Value::Null);
+    }
+}
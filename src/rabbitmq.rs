@@ -1,15 +1,24 @@
-use crate::types::rmq_types::{RemoteQueue, RmqClient, RmqConnectionInfo};
+use crate::config::Credentials;
+use crate::dtos::MessageContent;
+use crate::transport::amqp::AmqpTransport;
+use crate::transport::http::HttpTransport;
+use crate::transport::{MessageTaker, Transport};
+use crate::types::rmq_types::{
+    RemoteExchange, RemoteQueue, RemoteVhost, RmqClient, RmqConnectionInfo,
+};
 use anyhow::{anyhow, Context};
 use log::info;
 use rabbitmq_http_client::api::{Client, HttpClientError};
-use rabbitmq_http_client::requests::shovels::MessageProperties;
-use rabbitmq_http_client::responses::GetMessage;
-use serde_json::Value;
 use thiserror::Error;
 use url::Url;
 
 pub struct Rabbitmq {
     client: RmqClient,
+    /// Reads and publishes messages, management calls always go through `client`
+    transport: Box<dyn Transport>,
+    endpoint: Url,
+    amqp_url: Option<Url>,
+    credentials: Credentials,
     domain: String,
     vhost: String,
     show_exclusive_queues: bool,
@@ -18,18 +27,29 @@ pub struct Rabbitmq {
 #[derive(Error, Debug)]
 pub enum RabbitMQError {
     #[error("{:?}", .0)]
-    HttpClientError(#[from] HttpClientError),
+    HttpClientError(#[from] Box<HttpClientError>),
+    #[error("{:?}", .0)]
+    Publish(#[from] reqwest::Error),
+    #[error("{:?}", .0)]
+    Amqp(#[from] lapin::Error),
     #[error("{:?}", .0)]
     Other(#[from] anyhow::Error),
 }
 
+impl From<HttpClientError> for RabbitMQError {
+    fn from(error: HttpClientError) -> Self {
+        RabbitMQError::HttpClientError(Box::new(error))
+    }
+}
+
 impl Rabbitmq {
     pub async fn connect(
-        url: &str,
+        url: &Url,
+        credentials: &Credentials,
         vhost: &str,
         show_exclusive_queues: bool,
+        amqp_url: Option<&Url>,
     ) -> Result<Self, anyhow::Error> {
-        let url = Url::parse(url)?;
         let domain = url.domain().expect("Domain is missing").to_string();
         let port = url.port().unwrap_or(443);
         let endpoint = format!("{}://{}:{}{}", url.scheme(), domain, port, url.path());
@@ -39,9 +59,9 @@ impl Rabbitmq {
             endpoint, vhost
         );
         let client = Client::new(
-            endpoint,
-            url.username().to_string(),
-            url.password().expect("Password is missing").to_string(),
+            endpoint.clone(),
+            credentials.username.clone(),
+            credentials.password.clone(),
         );
 
         // check connection by getting a cluster name
@@ -50,14 +70,56 @@ impl Rabbitmq {
             .await
             .context("Can't connect to RabbitMQ")?;
 
+        let endpoint = Url::parse(&endpoint)?;
+        let transport = new_transport(&endpoint, amqp_url, credentials, vhost)
+            .await
+            .context("Can't connect to RabbitMQ over AMQP")?;
+
         Ok(Self {
             client,
+            transport,
+            endpoint,
+            amqp_url: amqp_url.cloned(),
+            credentials: credentials.clone(),
             domain,
             vhost: vhost.to_string(),
             show_exclusive_queues,
         })
     }
 
+    /// Returns a client for another vhost of the same broker, or `None` if the vhost doesn't exist
+    pub async fn for_vhost(&self, vhost: &str) -> Result<Option<Self>, RabbitMQError> {
+        let client = Client::new(
+            self.endpoint.to_string(),
+            self.credentials.username.clone(),
+            self.credentials.password.clone(),
+        );
+        match client.get_vhost(vhost).await {
+            Ok(_) => {}
+            Err(e) if e.is_not_found() => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        let transport = new_transport(
+            &self.endpoint,
+            self.amqp_url.as_ref(),
+            &self.credentials,
+            vhost,
+        )
+        .await?;
+
+        Ok(Some(Self {
+            client,
+            transport,
+            endpoint: self.endpoint.clone(),
+            amqp_url: self.amqp_url.clone(),
+            credentials: self.credentials.clone(),
+            domain: self.domain.clone(),
+            vhost: vhost.to_string(),
+            show_exclusive_queues: self.show_exclusive_queues,
+        }))
+    }
+
     pub fn get_connection_info(&self) -> RmqConnectionInfo {
         RmqConnectionInfo {
             domain: self.domain.clone(),
@@ -65,6 +127,16 @@ impl Rabbitmq {
         }
     }
 
+    /// Name of the local database file. It includes the port so that brokers on the same host
+    /// don't share messages. Files of earlier versions were named only by the host
+    pub fn database_name(&self) -> String {
+        let port = self
+            .endpoint
+            .port_or_known_default()
+            .expect("Endpoint is an HTTP URL");
+        format!("{}_{}", self.domain, port)
+    }
+
     pub async fn list_queues(&self) -> Result<Vec<RemoteQueue>, RabbitMQError> {
         let queues = self
             .client
@@ -82,11 +154,40 @@ impl Rabbitmq {
         Ok(queues)
     }
 
-    pub async fn load_messages(
-        &self,
-        queue: &str,
-        peek: bool,
-    ) -> Result<Vec<GetMessage>, RabbitMQError> {
+    /// Returns the vhosts that the user can access
+    pub async fn list_vhosts(&self) -> Result<Vec<RemoteVhost>, RabbitMQError> {
+        let vhosts = self
+            .client
+            .list_vhosts()
+            .await?
+            .into_iter()
+            .map(|x| RemoteVhost {
+                name: x.name,
+                description: x.description.filter(|x| !x.is_empty()),
+            })
+            .collect();
+
+        Ok(vhosts)
+    }
+
+    pub async fn list_exchanges(&self) -> Result<Vec<RemoteExchange>, RabbitMQError> {
+        let exchanges = self
+            .client
+            .list_exchanges_in(&self.vhost)
+            .await?
+            .into_iter()
+            .map(|x| RemoteExchange {
+                name: x.name,
+                exchange_type: x.exchange_type,
+            })
+            .collect();
+
+        Ok(exchanges)
+    }
+
+    /// Returns the number of messages in the queue. Fails for exclusive queues
+    /// because their messages can't be read
+    pub async fn count_messages(&self, queue: &str) -> Result<u64, RabbitMQError> {
         let queue_info = self.client.get_queue_info(&self.vhost, queue).await?;
         if queue_info.exclusive {
             return Err(RabbitMQError::Other(anyhow!(
@@ -94,35 +195,88 @@ impl Rabbitmq {
                 queue
             )));
         }
+        Ok(queue_info.message_count)
+    }
 
-        let ack_mode = match peek {
-            true => "ack_requeue_true",
-            false => "ack_requeue_false",
-        };
+    /// Returns all messages in the queue leaving them in the queue
+    pub async fn peek_messages(&self, queue: &str) -> Result<Vec<MessageContent>, RabbitMQError> {
+        let message_count = self.count_messages(queue).await?;
+        self.transport
+            .peek_messages(queue, message_count as u32)
+            .await
+    }
 
-        let messages = self
-            .client
-            .get_messages(
-                &self.vhost,
-                queue,
-                queue_info.message_count as u32,
-                ack_mode,
-            )
-            .await?;
+    /// Starts taking messages from the head of the queue, they stay in the queue until
+    /// they are acknowledged. See `Transport::take_messages`
+    pub async fn take_messages(&self, queue: &str) -> Result<Box<dyn MessageTaker>, RabbitMQError> {
+        self.transport.take_messages(queue).await
+    }
+
+    /// Whether taken messages return to the queue until they are acknowledged,
+    /// see `Transport::keeps_unacked_messages`
+    pub fn keeps_unacked_messages(&self) -> bool {
+        self.transport.keeps_unacked_messages()
+    }
 
-        Ok(messages)
+    /// Whether published messages are confirmed by the broker, see `Transport::confirms_publishes`
+    pub fn confirms_publishes(&self) -> bool {
+        self.transport.confirms_publishes()
     }
 
+    /// Removes all ready messages from the queue and returns how many there were.
+    /// Unacknowledged messages of consumers stay in the queue
+    pub async fn purge_queue(&self, queue: &str) -> Result<u64, RabbitMQError> {
+        let message_count = self.count_messages(queue).await?;
+        self.client.purge_queue(&self.vhost, queue).await?;
+        Ok(message_count)
+    }
+
+    /// Publishes the message and returns whether it was routed to at least one queue
     pub async fn send_message(
         &self,
-        to_queue: &str,
-        payload: &str,
-        props: impl IntoIterator<Item = (String, Value)>,
-    ) -> Result<(), RabbitMQError> {
-        let properties = MessageProperties::from_iter(props);
-        self.client
-            .publish_message(&self.vhost, "", to_queue, payload, properties)
-            .await?;
-        Ok(())
+        exchange: &str,
+        routing_key: &str,
+        message: &MessageContent,
+    ) -> Result<bool, RabbitMQError> {
+        self.transport.publish(exchange, routing_key, message).await
+    }
+}
+
+#[cfg(test)]
+impl Rabbitmq {
+    /// Client that reads and publishes messages through the transport,
+    /// and makes management calls to the endpoint
+    pub fn with_transport(endpoint: Url, transport: Box<dyn Transport>) -> Self {
+        let credentials = Credentials {
+            username: "guest".to_string(),
+            password: "guest".to_string(),
+        };
+        Self {
+            client: Client::new(
+                endpoint.to_string(),
+                credentials.username.clone(),
+                credentials.password.clone(),
+            ),
+            transport,
+            endpoint,
+            amqp_url: None,
+            credentials,
+            domain: "localhost".to_string(),
+            vhost: "/".to_string(),
+            show_exclusive_queues: false,
+        }
     }
 }
+
+/// Uses AMQP when its URL is set and the management HTTP API otherwise
+async fn new_transport(
+    endpoint: &Url,
+    amqp_url: Option<&Url>,
+    credentials: &Credentials,
+    vhost: &str,
+) -> Result<Box<dyn Transport>, RabbitMQError> {
+    Ok(match amqp_url {
+        Some(amqp_url) => Box::new(AmqpTransport::connect(amqp_url, credentials, vhost).await?),
+        None => Box::new(HttpTransport::new(endpoint, credentials, vhost)),
+    })
+}
@@ -5,12 +5,23 @@ use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::watch::{Receiver, Sender};
 use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
 use tokio::time;
 
 #[derive(Clone)]
 pub struct RmqBackground {
     sender: Sender<Vec<QueueCounters>>,
     notify_worker: Arc<Notify>,
+    /// Stops the worker when the last clone is dropped
+    _worker: Arc<Worker>,
+}
+
+struct Worker(JoinHandle<()>);
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
 }
 
 impl RmqBackground {
@@ -20,7 +31,7 @@ impl RmqBackground {
 
         // start a task that waits for a Notify and then queries RMQ and sends counters until
         // there are receivers listening to it. After that it starts again from waiting for a Notify
-        {
+        let worker = {
             let notify_worker = Arc::clone(&notify_worker);
             let sender = sender.clone();
             tokio::spawn(async move {
@@ -28,12 +39,13 @@ impl RmqBackground {
                     notify_worker.notified().await;
                     update_counter(&sender, &rmq, update_interval).await;
                 }
-            });
-        }
+            })
+        };
 
         Self {
             sender,
             notify_worker,
+            _worker: Arc::new(Worker(worker)),
         }
     }
 
@@ -73,3 +85,29 @@ async fn update_counter(
         time::sleep(update_interval).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::fake::FakeTransport;
+    use url::Url;
+
+    #[tokio::test]
+    async fn worker_stops_when_dropped() {
+        let endpoint = Url::parse("http://localhost:15672/api").unwrap();
+        let rabbitmq = Arc::new(Rabbitmq::with_transport(
+            endpoint,
+            Box::new(FakeTransport::default()),
+        ));
+        let background = RmqBackground::new(Arc::clone(&rabbitmq), Duration::from_secs(1));
+        let clone = background.clone();
+
+        drop(background);
+        tokio::task::yield_now().await;
+        assert_eq!(Arc::strong_count(&rabbitmq), 2);
+
+        drop(clone);
+        tokio::task::yield_now().await;
+        assert_eq!(Arc::strong_count(&rabbitmq), 1);
+    }
+}
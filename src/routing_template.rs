@@ -0,0 +1,135 @@
+use crate::database::MessageId;
+use crate::dtos::Message;
+use serde_json::Value;
+use thiserror::Error;
+
+/// A string with placeholders that are replaced with values taken from a message.
+///
+/// Supported placeholders:
+/// - `{exchange}` and `{routing_key}` - the exchange and routing key the message was originally published with
+/// - `{headers.<name>}` - value of an application header
+/// - `{properties.<name>}` - value of a standard property, e.g. `{properties.type}`
+///
+/// Use `{{` and `}}` to insert literal braces
+pub struct RoutingTemplate {
+    segments: Vec<Segment>,
+}
+
+enum Segment {
+    Literal(String),
+    Exchange,
+    RoutingKey,
+    Header(String),
+    Property(String),
+}
+
+#[derive(Error, Debug)]
+pub enum TemplateError {
+    #[error("Template '{template}' is invalid: {reason}")]
+    Invalid { template: String, reason: String },
+    #[error("Message {message_id} has no value for placeholder '{placeholder}'")]
+    MissingValue {
+        message_id: MessageId,
+        placeholder: String,
+    },
+}
+
+impl RoutingTemplate {
+    pub fn parse(template: &str) -> Result<Self, TemplateError> {
+        let invalid = |reason: &str| TemplateError::Invalid {
+            template: template.to_string(),
+            reason: reason.to_string(),
+        };
+
+        let mut segments = vec![];
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => return Err(invalid("placeholder is not closed")),
+                        }
+                    }
+
+                    let segment = match placeholder.as_str() {
+                        "exchange" => Segment::Exchange,
+                        "routing_key" => Segment::RoutingKey,
+                        p => match p.split_once('.') {
+                            Some(("headers", name)) => Segment::Header(name.to_string()),
+                            Some(("properties", name)) => Segment::Property(name.to_string()),
+                            _ => return Err(invalid(&format!("unknown placeholder '{p}'"))),
+                        },
+                    };
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(segment);
+                }
+                '}' => return Err(invalid("unexpected '}', use '}}' to insert a brace")),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Self { segments })
+    }
+
+    pub fn render(&self, message: &Message) -> Result<String, TemplateError> {
+        let mut result = String::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(s) => result.push_str(s),
+                Segment::Exchange => result.push_str(&message.content.exchange),
+                Segment::RoutingKey => result.push_str(&message.content.routing_key),
+                Segment::Header(name) => {
+                    let value = message.content.headers.get(name);
+                    result.push_str(&value_to_string(value, message, "headers", name)?);
+                }
+                Segment::Property(name) => {
+                    let properties = serde_json::to_value(&message.content.properties)
+                        .expect("MessageProperties is always serializable");
+                    let value = properties.get(name);
+                    result.push_str(&value_to_string(value, message, "properties", name)?);
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+fn value_to_string(
+    value: Option<&Value>,
+    message: &Message,
+    kind: &str,
+    name: &str,
+) -> Result<String, TemplateError> {
+    match value {
+        Some(Value::String(s)) => Ok(s.clone()),
+        Some(Value::Number(n)) => Ok(n.to_string()),
+        Some(Value::Bool(b)) => Ok(b.to_string()),
+        _ => Err(TemplateError::MissingValue {
+            message_id: message.id,
+            placeholder: format!("{kind}.{name}"),
+        }),
+    }
+}
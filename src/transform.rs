@@ -0,0 +1,125 @@
+use crate::dtos::{MessageContent, PayloadEncoding, TransformationRequest};
+use json_patch::Patch;
+use regex::Regex;
+use serde_json::Value;
+use similar::TextDiff;
+use thiserror::Error;
+
+/// A change applied to the payloads of many messages at once
+pub enum Transformation {
+    /// RFC 6902 JSON Patch, applied to JSON payloads
+    JsonPatch(Patch),
+    /// Replaces all matches of the regex. `$1` or `${name}` in the replacement insert capture groups
+    RegexReplace { regex: Regex, replacement: String },
+}
+
+#[derive(Error, Debug)]
+pub enum TransformError {
+    #[error("JSON patch is invalid: {}", .0)]
+    InvalidPatch(serde_json::Error),
+    #[error("Regex is invalid: {}", .0)]
+    InvalidRegex(#[from] regex::Error),
+}
+
+impl Transformation {
+    pub fn from_request(request: &TransformationRequest) -> Result<Self, TransformError> {
+        let transformation = match request {
+            TransformationRequest::JsonPatch { patch } => Transformation::JsonPatch(
+                serde_json::from_value(patch.clone()).map_err(TransformError::InvalidPatch)?,
+            ),
+            TransformationRequest::RegexReplace {
+                pattern,
+                replacement,
+            } => Transformation::RegexReplace {
+                regex: Regex::new(pattern)?,
+                replacement: replacement.clone(),
+            },
+        };
+        Ok(transformation)
+    }
+
+    /// Returns the new payload, or the reason why the message can't be transformed
+    pub fn apply(&self, message: &MessageContent) -> Result<String, String> {
+        if message.payload_encoding != PayloadEncoding::String {
+            return Err("binary payloads can't be transformed".to_string());
+        }
+
+        match self {
+            Transformation::JsonPatch(patch) => {
+                let mut json: Value = serde_json::from_str(&message.payload)
+                    .map_err(|e| format!("payload isn't JSON: {e}"))?;
+                json_patch::patch(&mut json, patch).map_err(|e| e.to_string())?;
+                // keys keep their order, and pretty-printed payloads stay pretty-printed
+                let payload = if message.payload.trim().contains('\n') {
+                    serde_json::to_string_pretty(&json)
+                } else {
+                    serde_json::to_string(&json)
+                };
+                payload.map_err(|e| e.to_string())
+            }
+            Transformation::RegexReplace { regex, replacement } => {
+                let payload = regex
+                    .replace_all(&message.payload, replacement.as_str())
+                    .into_owned();
+
+                // a replacement must not break JSON payloads
+                let was_json = serde_json::from_str::<Value>(&message.payload).is_ok();
+                if was_json && let Err(e) = serde_json::from_str::<Value>(&payload) {
+                    return Err(format!("result isn't valid JSON: {e}"));
+                }
+                Ok(payload)
+            }
+        }
+    }
+}
+
+/// Unified diff of the payloads. JSON payloads are pretty-printed
+/// so that the diff shows the changed fields rather than one long line
+pub fn payload_diff(old: &str, new: &str) -> String {
+    let pretty = |payload: &str| {
+        serde_json::from_str::<Value>(payload)
+            .ok()
+            .and_then(|json| serde_json::to_string_pretty(&json).ok())
+            .unwrap_or_else(|| payload.to_string())
+    };
+    let (old, new) = (pretty(old), pretty(new));
+
+    TextDiff::from_lines(&old, &new)
+        .unified_diff()
+        .context_radius(2)
+        .header("before", "after")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn json_patch(patch: Value) -> Transformation {
+        Transformation::from_request(&TransformationRequest::JsonPatch { patch }).unwrap()
+    }
+
+    fn message(payload: &str) -> MessageContent {
+        serde_json::from_value(json!({ "payload": payload })).unwrap()
+    }
+
+    #[test]
+    fn json_patch_keeps_key_order() {
+        let patch = json_patch(json!([{ "op": "replace", "path": "/b", "value": 3 }]));
+
+        let payload = patch.apply(&message(r#"{"z":1,"b":2,"a":[1,2]}"#)).unwrap();
+
+        assert_eq!(payload, r#"{"z":1,"b":3,"a":[1,2]}"#);
+    }
+
+    #[test]
+    fn json_patch_keeps_pretty_printing() {
+        let patch = json_patch(json!([{ "op": "remove", "path": "/b" }]));
+        let payload = "{\n  \"z\": 1,\n  \"b\": 2,\n  \"a\": true\n}";
+
+        let payload = patch.apply(&message(payload)).unwrap();
+
+        assert_eq!(payload, "{\n  \"z\": 1,\n  \"a\": true\n}");
+    }
+}
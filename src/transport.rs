@@ -0,0 +1,63 @@
+//! How messages are read from queues and published. Listing queues, exchanges and other
+//! management calls always go through the management HTTP API
+use crate::dtos::MessageContent;
+use crate::rabbitmq::RabbitMQError;
+use async_trait::async_trait;
+
+pub mod amqp;
+#[cfg(test)]
+pub mod fake;
+pub mod http;
+
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Returns up to `count` messages from the head of the queue, leaving them in the queue
+    async fn peek_messages(
+        &self,
+        queue: &str,
+        count: u32,
+    ) -> Result<Vec<MessageContent>, RabbitMQError>;
+
+    /// Starts taking messages from the head of the queue without acknowledging them.
+    /// The messages are removed from the queue by `MessageTaker::ack`
+    async fn take_messages(&self, queue: &str) -> Result<Box<dyn MessageTaker>, RabbitMQError>;
+
+    /// Whether taken messages stay on the broker until they are acknowledged,
+    /// so that they return to the queue if the process stops before that.
+    /// Otherwise they are lost in that case
+    fn keeps_unacked_messages(&self) -> bool;
+
+    /// Whether `publish` returns only after the broker has taken responsibility for the message,
+    /// i.e. written it to disk if it's persistent and routed to durable queues
+    fn confirms_publishes(&self) -> bool;
+
+    /// Publishes the message and returns whether it was routed to at least one queue
+    async fn publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        message: &MessageContent,
+    ) -> Result<bool, RabbitMQError>;
+}
+
+/// A message taken from a queue and not acknowledged yet
+pub struct TakenMessage {
+    /// Identifies the message in `MessageTaker::ack`
+    pub tag: u64,
+    pub content: MessageContent,
+}
+
+/// Takes messages from one queue batch by batch. All of them stay unacknowledged until
+/// they are acknowledged one by one or released, so messages that are skipped in one batch
+/// aren't taken again in the next one
+#[async_trait]
+pub trait MessageTaker: Send {
+    /// Takes up to `count` messages that follow the ones taken before
+    async fn take(&mut self, count: u32) -> Result<Vec<TakenMessage>, RabbitMQError>;
+
+    /// Removes the messages from the queue
+    async fn ack(&mut self, tags: &[u64]) -> Result<(), RabbitMQError>;
+
+    /// Returns all messages that weren't acknowledged to the queue
+    async fn release(self: Box<Self>) -> Result<(), RabbitMQError>;
+}
@@ -0,0 +1,145 @@
+//! In-memory broker with a single queue, for tests of operations
+use crate::dtos::MessageContent;
+use crate::rabbitmq::RabbitMQError;
+use crate::transport::{MessageTaker, TakenMessage, Transport};
+use async_trait::async_trait;
+use axum::extract::{Path, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+#[derive(Clone, Default)]
+pub struct FakeTransport {
+    /// Messages in the queue, the head first
+    pub queue: Arc<Mutex<VecDeque<MessageContent>>>,
+    /// Published messages with their exchanges and routing keys
+    pub published: Arc<Mutex<Vec<(String, String, MessageContent)>>>,
+    /// Keeps unacknowledged messages and confirms publishes, like AMQP
+    pub amqp: bool,
+}
+
+impl FakeTransport {
+    pub fn payloads(&self) -> Vec<String> {
+        let queue = self.queue.lock().unwrap();
+        queue.iter().map(|x| x.payload.clone()).collect()
+    }
+
+    /// Serves the management API call that counts messages in the queue,
+    /// and returns its endpoint
+    pub async fn serve_management_api(&self) -> Url {
+        async fn queue_info(
+            State(transport): State<FakeTransport>,
+            Path((vhost, name)): Path<(String, String)>,
+        ) -> Json<Value> {
+            let messages = transport.queue.lock().unwrap().len();
+            Json(json!({
+                "name": name,
+                "vhost": vhost,
+                "type": "classic",
+                "durable": true,
+                "auto_delete": false,
+                "exclusive": false,
+                "arguments": {},
+                "messages": messages,
+            }))
+        }
+
+        let router = Router::new()
+            .route("/api/queues/{vhost}/{name}", get(queue_info))
+            .with_state(self.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        Url::parse(&format!("http://{address}/api")).unwrap()
+    }
+}
+
+#[async_trait]
+impl Transport for FakeTransport {
+    async fn peek_messages(
+        &self,
+        _queue: &str,
+        count: u32,
+    ) -> Result<Vec<MessageContent>, RabbitMQError> {
+        let queue = self.queue.lock().unwrap();
+        Ok(queue.iter().take(count as usize).cloned().collect())
+    }
+
+    async fn take_messages(&self, _queue: &str) -> Result<Box<dyn MessageTaker>, RabbitMQError> {
+        Ok(Box::new(FakeMessageTaker {
+            queue: Arc::clone(&self.queue),
+            requeue: self.amqp,
+            next_tag: 1,
+            unacked: BTreeMap::new(),
+        }))
+    }
+
+    fn keeps_unacked_messages(&self) -> bool {
+        self.amqp
+    }
+
+    fn confirms_publishes(&self) -> bool {
+        self.amqp
+    }
+
+    async fn publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        message: &MessageContent,
+    ) -> Result<bool, RabbitMQError> {
+        self.published.lock().unwrap().push((
+            exchange.to_string(),
+            routing_key.to_string(),
+            message.clone(),
+        ));
+        Ok(true)
+    }
+}
+
+struct FakeMessageTaker {
+    queue: Arc<Mutex<VecDeque<MessageContent>>>,
+    requeue: bool,
+    next_tag: u64,
+    unacked: BTreeMap<u64, MessageContent>,
+}
+
+#[async_trait]
+impl MessageTaker for FakeMessageTaker {
+    async fn take(&mut self, count: u32) -> Result<Vec<TakenMessage>, RabbitMQError> {
+        let mut queue = self.queue.lock().unwrap();
+        let count = queue.len().min(count as usize);
+        let mut taken = vec![];
+        for content in queue.drain(..count) {
+            let tag = self.next_tag;
+            self.next_tag += 1;
+            self.unacked.insert(tag, content.clone());
+            taken.push(TakenMessage { tag, content });
+        }
+        Ok(taken)
+    }
+
+    async fn ack(&mut self, tags: &[u64]) -> Result<(), RabbitMQError> {
+        for tag in tags {
+            self.unacked.remove(tag);
+        }
+        Ok(())
+    }
+
+    /// Returns the messages to the head of the queue in their order like a nack with requeue,
+    /// or publishes them to the end of the queue like the management API
+    async fn release(self: Box<Self>) -> Result<(), RabbitMQError> {
+        let mut queue = self.queue.lock().unwrap();
+        if self.requeue {
+            for message in self.unacked.into_values().rev() {
+                queue.push_front(message);
+            }
+        } else {
+            queue.extend(self.unacked.into_values());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+use crate::config::Credentials;
+use crate::dtos::{MessageContent, MessageProperties, PayloadEncoding};
+use crate::rabbitmq::RabbitMQError;
+use crate::transport::{MessageTaker, TakenMessage, Transport};
+use crate::types::rmq_types::RmqClient;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use rabbitmq_http_client::api::Client;
+use rabbitmq_http_client::responses::{GetMessage, MessageRouted};
+use serde_json::json;
+use std::collections::BTreeMap;
+use url::Url;
+
+/// Reads and publishes messages through the management HTTP API. It needs no other port
+/// to be open, but makes one request per published message and can't use publisher confirms
+pub struct HttpTransport {
+    client: RmqClient,
+    publisher: Publisher,
+}
+
+impl HttpTransport {
+    pub fn new(endpoint: &Url, credentials: &Credentials, vhost: &str) -> Self {
+        Self {
+            client: Client::new(
+                endpoint.to_string(),
+                credentials.username.clone(),
+                credentials.password.clone(),
+            ),
+            publisher: Publisher {
+                http_client: reqwest::Client::new(),
+                endpoint: endpoint.clone(),
+                credentials: credentials.clone(),
+                vhost: vhost.to_string(),
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for HttpTransport {
+    async fn peek_messages(
+        &self,
+        queue: &str,
+        count: u32,
+    ) -> Result<Vec<MessageContent>, RabbitMQError> {
+        let messages = self
+            .client
+            .get_messages(&self.publisher.vhost, queue, count, "ack_requeue_true")
+            .await?;
+        Ok(messages.into_iter().map(new_message).collect())
+    }
+
+    /// The management API can't leave messages unacknowledged, so they are removed
+    /// from the queue right away and published back to it on `release`
+    async fn take_messages(&self, queue: &str) -> Result<Box<dyn MessageTaker>, RabbitMQError> {
+        Ok(Box::new(HttpMessageTaker {
+            client: Client::new(
+                self.publisher.endpoint.to_string(),
+                self.publisher.credentials.username.clone(),
+                self.publisher.credentials.password.clone(),
+            ),
+            publisher: self.publisher.clone(),
+            queue: queue.to_string(),
+            next_tag: 1,
+            unacked: BTreeMap::new(),
+        }))
+    }
+
+    fn keeps_unacked_messages(&self) -> bool {
+        false
+    }
+
+    fn confirms_publishes(&self) -> bool {
+        false
+    }
+
+    async fn publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        message: &MessageContent,
+    ) -> Result<bool, RabbitMQError> {
+        self.publisher.publish(exchange, routing_key, message).await
+    }
+}
+
+// `publish_message` of rabbitmq_http_client always sends payloads as strings,
+// so publishing goes through a plain HTTP client to be able to send base64 payloads
+#[derive(Clone)]
+struct Publisher {
+    http_client: reqwest::Client,
+    endpoint: Url,
+    credentials: Credentials,
+    vhost: String,
+}
+
+impl Publisher {
+    async fn publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        message: &MessageContent,
+    ) -> Result<bool, RabbitMQError> {
+        let body = json!({
+            "routing_key": routing_key,
+            "payload": message.payload,
+            "payload_encoding": message.payload_encoding.as_str(),
+            "properties": message.properties.to_rmq_properties(&message.headers),
+        });
+
+        let response: MessageRouted = self
+            .http_client
+            .post(self.publish_url(exchange))
+            .basic_auth(&self.credentials.username, Some(&self.credentials.password))
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.routed)
+    }
+
+    fn publish_url(&self, exchange: &str) -> Url {
+        // the default exchange is addressed by this name in the management API
+        let exchange = match exchange {
+            "" => "amq.default",
+            name => name,
+        };
+
+        let mut url = self.endpoint.clone();
+        url.path_segments_mut()
+            .expect("Endpoint URL cannot be a base")
+            .pop_if_empty()
+            .extend(["exchanges", &self.vhost, exchange, "publish"]);
+        url
+    }
+}
+
+/// Keeps copies of taken messages to publish them back. Unlike with AMQP, the messages
+/// are lost if the process stops before that. Published messages get behind the messages
+/// that are in the queue, with the default exchange and the queue name as the routing key
+struct HttpMessageTaker {
+    client: RmqClient,
+    publisher: Publisher,
+    queue: String,
+    next_tag: u64,
+    unacked: BTreeMap<u64, MessageContent>,
+}
+
+#[async_trait]
+impl MessageTaker for HttpMessageTaker {
+    async fn take(&mut self, count: u32) -> Result<Vec<TakenMessage>, RabbitMQError> {
+        let messages = self
+            .client
+            .get_messages(
+                &self.publisher.vhost,
+                &self.queue,
+                count,
+                "ack_requeue_false",
+            )
+            .await?;
+
+        let mut taken = vec![];
+        for message in messages {
+            let tag = self.next_tag;
+            self.next_tag += 1;
+            let content = new_message(message);
+            self.unacked.insert(tag, content.clone());
+            taken.push(TakenMessage { tag, content });
+        }
+        Ok(taken)
+    }
+
+    async fn ack(&mut self, tags: &[u64]) -> Result<(), RabbitMQError> {
+        for tag in tags {
+            self.unacked.remove(tag);
+        }
+        Ok(())
+    }
+
+    async fn release(self: Box<Self>) -> Result<(), RabbitMQError> {
+        // the default exchange routes messages to the queue with the same name
+        for message in self.unacked.values() {
+            if !self.publisher.publish("", &self.queue, message).await? {
+                return Err(RabbitMQError::Other(anyhow!(
+                    "Couldn't return a message to queue {}, it wasn't routed",
+                    self.queue
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn new_message(message: GetMessage) -> MessageContent {
+    let (properties, headers) = MessageProperties::from_rmq_properties(message.properties.0);
+    MessageContent {
+        // RabbitMQ returns "base64" for payloads that aren't valid UTF-8 and "string" otherwise
+        payload_encoding: PayloadEncoding::parse(&message.payload_encoding)
+            .unwrap_or(PayloadEncoding::String),
+        payload: message.payload,
+        properties,
+        headers,
+        exchange: message.exchange,
+        routing_key: message.routing_key,
+        redelivered: message.redelivered,
+    }
+}
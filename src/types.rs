@@ -2,20 +2,30 @@ pub mod rmq_types {
     use rabbitmq_http_client::api::Client;
 
     pub type RmqClient = Client<String, String, String>;
-    
+
     pub struct RemoteQueue {
         pub name: String,
         pub message_count: u64,
         pub exclusive: bool,
     }
 
+    pub struct RemoteExchange {
+        pub name: String,
+        pub exchange_type: String,
+    }
+
+    pub struct RemoteVhost {
+        pub name: String,
+        pub description: Option<String>,
+    }
+
     pub struct RmqConnectionInfo {
         pub domain: String,
         pub vhost: String,
     }
 }
 
-pub mod db_types{
+pub mod db_types {
     use crate::database::QueueId;
 
     pub struct LocalQueue {
@@ -23,4 +33,4 @@ pub mod db_types{
         pub name: String,
         pub message_count: u32,
     }
-}
\ No newline at end of file
+}